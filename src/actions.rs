@@ -1,133 +1,1654 @@
-use std::fmt::{Display, Write, Debug};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::{Debug, Display, Write};
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use regex::Regex;
+
+use crate::error::{Diagnostic, ErrorKind, SceneError};
+use crate::pacing::Pacing;
+use crate::redact::Redaction;
+use crate::{EXPECT_TIMEOUT, FAKE_OUTPUT_DELAY, WAIT_TIMEOUT};
+
+#[derive(Debug)]
+pub struct Action {
+    pub kind: ActionKind,
+    // 1-based line in the scene file the action was parsed from
+    pub line: usize,
+    // text of the `@note` annotations directly preceding the action
+    pub note: Option<Box<str>>,
+    // what to do when the action's command fails
+    pub on_error: OnError,
+    // how the exit code of the previous command is shown in the action's prompt
+    pub show_status: ShowStatus,
+    // `@live`: the command runs even when replaying recorded outputs
+    pub live: bool,
+}
+
+/// What to do when a command fails to run or exits with a non-zero status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    // keep going with the next action
+    Continue,
+    // end the scene
+    Stop,
+    // ask whether to retry the action, skip it or end the scene
+    Prompt,
+}
+
+impl OnError {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "continue" => Some(Self::Continue),
+            "stop" => Some(Self::Stop),
+            "prompt" => Some(Self::Prompt),
+            _ => None,
+        }
+    }
+}
+
+impl Display for OnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Continue => "continue",
+            Self::Stop => "stop",
+            Self::Prompt => "prompt",
+        })
+    }
+}
+
+/// How a failed exit code is shown in front of the next prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShowStatus {
+    Off,
+    // `[1] ` like zsh
+    Code,
+    // a red `✗ `
+    Mark,
+}
+
+impl ShowStatus {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "code" => Some(Self::Code),
+            "mark" => Some(Self::Mark),
+            _ => None,
+        }
+    }
+}
+
+/// How a `+` action shows the new contents of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditMode {
+    // types the whole file into an empty editor
+    Retype,
+    // shows the file as it was and only types the lines that changed
+    Diff,
+}
+
+impl EditMode {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "retype" => Some(Self::Retype),
+            "diff" => Some(Self::Diff),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.kind, f)
+    }
+}
 
 #[derive(Debug)]
-pub enum Action {
-    // prints `cd {0}` and changes the directory
-    ChangeDir(Box<str>),
+pub enum ActionKind {
+    // prints {1}, the command as written in the scene, and changes the directory to {0}
+    ChangeDir(Box<str>, Box<str>),
     // changes the directory
     ChangeDirQuiet(Box<str>),
     // prints `{0}` and runs it as a command
-    RunCommand(Box<[Box<str>]>),
+    RunCommand(CommandLine),
     // runs the command without showing its output
-    RunCommandQuiet(Box<[Box<str>]>),
+    RunCommandQuiet(CommandLine),
     // runs the command
-    RunCommandOnlyOutput(Box<[Box<str>]>),
-    // prints `edit {0}`, copies file from {2} to {1} and runs the editor on {1}
-    RunEditor(Box<str>, Box<str>),
+    RunCommandOnlyOutput(CommandLine),
+    // prints `{0}` followed by the heredoc in {1} and runs {0} with the heredoc as its input
+    RunCommandWithInput(CommandLine, Heredoc),
+    // prints `edit {3}`, {0} as written in the scene, copies file from {1} to {0}
+    // and runs the editor on {0}, showing the new contents as {2} says
+    RunEditor(Box<str>, Box<str>, EditMode, Box<str>),
+    // starts the command in the background under the name {0}
+    RunBackground(Box<str>, CommandLine),
+    // stops the background process named {0}
+    KillBackground(Box<str>),
+    // waits for the background process named {0} to exit, for at most {1}
+    WaitBackground(Box<str>, Option<Duration>),
+    // waits until {0} holds, for at most {1}
+    WaitFor(Condition, Duration),
+    // prints `{0}` like `RunCommand`, but prints the lines of {1} with {2} between
+    // them instead of running it
+    FakeCommand(CommandLine, Box<str>, Duration),
 }
 
-impl Display for Action {
+#[derive(Debug)]
+pub enum Condition {
+    // the file exists
+    File(Box<str>),
+    // the TCP port on localhost accepts connections
+    Port(u16),
+    // the output of the background process named {0} matches {1}
+    Output(Box<str>, Regex),
+    // the time has passed
+    Duration(Duration),
+}
+
+impl Display for Condition {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::ChangeDir(dir) => f.write_fmt(format_args!("cd {dir:?}")),
+            Self::File(path) => f.write_fmt(format_args!("file {path} to exist")),
+            Self::Port(port) => f.write_fmt(format_args!("port {port} to accept connections")),
+            Self::Output(name, regex) => {
+                f.write_fmt(format_args!("the output of `{name}` to match `{regex}`"))
+            }
+            Self::Duration(duration) => f.write_fmt(format_args!("{duration:?}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CommandLine {
+    pub args: Box<[Box<str>]>,
+    // the command as written in the scene with variables substituted, including
+    // quotes and `\` line continuations but without a trailing comment
+    pub text: Box<str>,
+    // each of `args` as written in `text`
+    pub words: Box<[Box<str>]>,
+    // `text` with the substituted values quoted, so a shell sees each of them as-is
+    pub script: Box<str>,
+    // when set, `script` is run with `{shell} -c` instead of running `args` directly
+    pub shell: Option<Box<str>>,
+    // prompts the command is expected to show and their answers, in order
+    pub expect: Vec<Expectation>,
+    // the command is killed when it runs for longer than this
+    pub timeout: Option<Duration>,
+    // how its output is shown
+    pub pacing: Pacing,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expectation {
+    pub prompt: Box<str>,
+    // typed after `prompt` shows up, followed by enter
+    pub answer: Box<str>,
+    pub timeout: Duration,
+}
+
+impl Display for CommandLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for i in 0..self.args.len() {
+            if i != 0 {
+                f.write_char(' ')?;
+            }
+            Debug::fmt(&self.args[i], f)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct Heredoc {
+    pub delimiter: Box<str>,
+    // every line of the heredoc, each ending in a newline
+    pub body: Box<str>,
+}
+
+impl ActionKind {
+    pub fn command_line(&self) -> Option<&CommandLine> {
+        match self {
+            Self::RunCommand(cmd)
+            | Self::RunCommandQuiet(cmd)
+            | Self::RunCommandOnlyOutput(cmd)
+            | Self::RunCommandWithInput(cmd, _)
+            | Self::RunBackground(_, cmd)
+            | Self::FakeCommand(cmd, ..) => Some(cmd),
+            Self::ChangeDir(..)
+            | Self::ChangeDirQuiet(_)
+            | Self::RunEditor(..)
+            | Self::KillBackground(_)
+            | Self::WaitBackground(..)
+            | Self::WaitFor(..) => None,
+        }
+    }
+
+    pub fn command_line_mut(&mut self) -> Option<&mut CommandLine> {
+        match self {
+            Self::RunCommand(cmd)
+            | Self::RunCommandQuiet(cmd)
+            | Self::RunCommandOnlyOutput(cmd)
+            | Self::RunCommandWithInput(cmd, _)
+            | Self::RunBackground(_, cmd)
+            | Self::FakeCommand(cmd, ..) => Some(cmd),
+            Self::ChangeDir(..)
+            | Self::ChangeDirQuiet(_)
+            | Self::RunEditor(..)
+            | Self::KillBackground(_)
+            | Self::WaitBackground(..)
+            | Self::WaitFor(..) => None,
+        }
+    }
+}
+
+impl Display for ActionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChangeDir(dir, _) => f.write_fmt(format_args!("cd {dir:?}")),
             Self::ChangeDirQuiet(dir) => f.write_fmt(format_args!("#cd {dir:?}")),
-            Self::RunEditor(dst, src) => f.write_fmt(format_args!("+ {dst:?} {src:?}")),
-            Self::RunCommand(args) => {
-                for i in 0..args.len() {
-                    if i != 0 {
-                        f.write_char(' ')?;
+            Self::RunEditor(dst, src, ..) => f.write_fmt(format_args!("+ {dst:?} {src:?}")),
+            Self::RunCommand(cmd) | Self::FakeCommand(cmd, ..) => Display::fmt(cmd, f),
+            Self::RunCommandQuiet(cmd) => {
+                f.write_char('#')?;
+                Display::fmt(cmd, f)
+            }
+            Self::RunCommandOnlyOutput(cmd) => {
+                f.write_char('-')?;
+                Display::fmt(cmd, f)
+            }
+            Self::RunCommandWithInput(cmd, heredoc) => {
+                Display::fmt(cmd, f)?;
+                f.write_char('\n')?;
+                f.write_str(&heredoc.body)?;
+                f.write_str(&heredoc.delimiter)
+            }
+            Self::RunBackground(_, cmd) => {
+                f.write_char('&')?;
+                Display::fmt(cmd, f)
+            }
+            Self::KillBackground(name) => f.write_fmt(format_args!("kill &{name}")),
+            Self::WaitBackground(name, _) => f.write_fmt(format_args!("wait &{name}")),
+            Self::WaitFor(condition, _) => match condition {
+                Condition::File(path) => f.write_fmt(format_args!("wait file {path:?}")),
+                Condition::Port(port) => f.write_fmt(format_args!("wait port {port}")),
+                Condition::Output(name, regex) => {
+                    f.write_fmt(format_args!("wait output &{name} {:?}", regex.as_str()))
+                }
+                Condition::Duration(duration) => f.write_fmt(format_args!("wait {duration:?}")),
+            },
+        }
+    }
+}
+
+/// Values for `${name}` substitutions. `--var` overrides take precedence over
+/// `let` declarations in the scene, which take precedence over the environment.
+pub struct Variables<'a> {
+    overrides: &'a HashMap<Box<str>, Box<str>>,
+    declared: HashMap<Box<str>, Box<str>>,
+}
+
+impl<'a> Variables<'a> {
+    pub fn new(overrides: &'a HashMap<Box<str>, Box<str>>) -> Self {
+        Self {
+            overrides,
+            declared: HashMap::new(),
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<Box<str>> {
+        if let Some(value) = self.overrides.get(name).or_else(|| self.declared.get(name)) {
+            return Some(value.clone());
+        }
+        env::var(name).ok().map(String::into_boxed_str)
+    }
+}
+
+/// A parsed scene file, including the scenes it includes.
+pub struct Scene {
+    pub actions: Vec<Action>,
+    // the `redact` rules for the output, in the order they were declared in
+    pub redactions: Vec<Redaction>,
+}
+
+pub fn parse_scene(
+    path: &Path,
+    contents: String,
+    overrides: &HashMap<Box<str>, Box<str>>,
+) -> Result<Scene, SceneError> {
+    let mut parser = SceneParser {
+        variables: Variables::new(overrides),
+        shell: None,
+        on_error: OnError::Continue,
+        show_status: ShowStatus::Off,
+        output_delay: FAKE_OUTPUT_DELAY,
+        pacing: Pacing::default(),
+        edit_mode: EditMode::Retype,
+        background: HashSet::new(),
+        redactions: vec![],
+        files: vec![],
+        include_lines: vec![],
+        diagnostics: vec![],
+    };
+    let actions = parser.parse_file(path, &contents);
+
+    if !parser.diagnostics.is_empty() {
+        return Err(SceneError {
+            diagnostics: parser.diagnostics,
+        });
+    }
+
+    Ok(Scene {
+        actions,
+        redactions: parser.redactions,
+    })
+}
+
+const ON_ERROR_VALUES: &str = "`continue`, `stop` or `prompt`";
+
+struct SceneParser<'a> {
+    variables: Variables<'a>,
+    // the shell commands are run with, as set by `set shell`
+    shell: Option<Box<str>>,
+    // as set by `set on_error`
+    on_error: OnError,
+    // as set by `set status`
+    show_status: ShowStatus,
+    // as set by `set output_delay`
+    output_delay: Duration,
+    // as set by `set output_rate` and `set output_lines`
+    pacing: Pacing,
+    // as set by `set edit`
+    edit_mode: EditMode,
+    // names of the background processes started so far
+    background: HashSet<Box<str>>,
+    redactions: Vec<Redaction>,
+    // the scene files currently being parsed, outermost first, and their canonical paths
+    files: Vec<(PathBuf, PathBuf)>,
+    // 0-based line of the `include` in each file that is currently being spliced
+    include_lines: Vec<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl SceneParser<'_> {
+    fn report(&mut self, mut diagnostic: Diagnostic) {
+        if let Some((path, _)) = self.files.last() {
+            diagnostic.path = path.as_path().into();
+        }
+        diagnostic.included_from = self
+            .files
+            .iter()
+            .zip(&self.include_lines)
+            .rev()
+            .map(|((path, _), line)| (path.clone(), line + 1))
+            .collect();
+        self.diagnostics.push(diagnostic);
+    }
+
+    fn parse_file(&mut self, path: &Path, contents: &str) -> Vec<Action> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.files.push((path.to_path_buf(), canonical));
+
+        let mut actions: Vec<Action> = Vec::new();
+        let mut note: Option<(usize, String)> = None;
+        // `@shell` or `@exec` for the next command
+        let mut shell: Option<Option<Box<str>>> = None;
+        // `@on_error` for the next action
+        let mut on_error: Option<OnError> = None;
+        // `@timeout` and `@name` for the next action
+        let mut timeout: Option<Duration> = None;
+        let mut background_name: Option<Box<str>> = None;
+        // `@output` for the next command
+        let mut fake_output: Option<Box<str>> = None;
+        // `@live` for the next command
+        let mut live = false;
+
+        let lines = contents.lines().collect::<Vec<_>>();
+        let mut next = 0;
+        while next < lines.len() {
+            let line_no = next;
+            // lines ending in `\` continue on the next one
+            let mut logical = lines[next].to_string();
+            while is_continued(&logical) && next + 1 < lines.len() {
+                next += 1;
+                logical.push('\n');
+                logical.push_str(lines[next]);
+            }
+            next += 1;
+
+            let line = &*logical;
+            let indent = line.len() - line.trim_start().len();
+            let content = &line[indent..];
+
+            if content.starts_with("//") {
+                continue;
+            }
+            if let Some(annotation) = content.strip_prefix('@') {
+                let name = annotation.split(' ').next().unwrap_or_default();
+                let value = annotation[name.len()..].trim();
+                let value_start = line.trim_end().len() - value.len();
+                let invalid_value =
+                    |kind| Diagnostic::new(line_no, line, value_start..line.trim_end().len(), kind);
+                match name {
+                    "note" => match &mut note {
+                        Some((_, note)) => {
+                            note.push('\n');
+                            note.push_str(value);
+                        }
+                        None => note = Some((line_no, value.to_string())),
+                    },
+                    "shell" => shell = Some(Some(default_shell())),
+                    "exec" => shell = Some(None),
+                    "live" => live = true,
+                    "on_error" => match OnError::parse(value) {
+                        Some(value) => on_error = Some(value),
+                        None => self.report(invalid_value(ErrorKind::InvalidSettingValue(
+                            value.into(),
+                            ON_ERROR_VALUES,
+                        ))),
+                    },
+                    "timeout" => match parse_duration(value) {
+                        Some(duration) => timeout = Some(duration),
+                        None => {
+                            self.report(invalid_value(ErrorKind::InvalidDuration(value.into())))
+                        }
+                    },
+                    "output" => {
+                        let output =
+                            self.read_output(path, &lines, &mut next, line_no, line, value_start);
+                        if output.is_some() {
+                            fake_output = output;
+                        }
                     }
-                    Debug::fmt(&args[i], f)?;
+                    "name" => match value {
+                        "" => self.report(invalid_value(ErrorKind::InvalidSettingValue(
+                            value.into(),
+                            "a name",
+                        ))),
+                        _ => background_name = Some(value.into()),
+                    },
+                    _ => self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        indent..indent + 1 + name.len(),
+                        ErrorKind::UnknownAnnotation(name.into()),
+                    )),
                 }
-                Ok(())
+                continue;
             }
-            Self::RunCommandQuiet(args) => {
-                f.write_char('#')?;
-                for i in 0..args.len() {
-                    if i != 0 {
-                        f.write_char(' ')?;
+
+            if let Some(setting) = content.strip_prefix("set ") {
+                let offset = indent + 4;
+                let result = parse_command(setting, &self.variables)
+                    .and_then(|words| self.apply_setting(setting, &words.args));
+                if let Err(CommandError { span, kind }) = result {
+                    self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        offset + span.start..offset + span.end,
+                        kind,
+                    ));
+                }
+                continue;
+            }
+
+            if let Some(rule) = content.strip_prefix("redact ") {
+                let offset = indent + 7;
+                let result = parse_command(rule, &self.variables)
+                    .and_then(|words| parse_redaction(rule, &words.args));
+                match result {
+                    Ok(Some(redaction)) => self.redactions.push(redaction),
+                    // there is nothing to redact on this machine
+                    Ok(None) => (),
+                    Err(CommandError { span, kind }) => self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        offset + span.start..offset + span.end,
+                        kind,
+                    )),
+                }
+                continue;
+            }
+
+            if let Some(declaration) = content.strip_prefix("let ") {
+                let offset = indent + 4;
+                match parse_let(declaration, &self.variables) {
+                    Ok((name, value)) => _ = self.variables.declared.insert(name, value),
+                    Err(CommandError { span, kind }) => self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        offset + span.start..offset + span.end,
+                        kind,
+                    )),
+                }
+                continue;
+            }
+
+            if let Some(expectation) = content.strip_prefix("expect ") {
+                let offset = indent + 7;
+                let span = offset..line.trim_end().len();
+                let cmd = match actions.last_mut().map(|action| &mut action.kind) {
+                    Some(ActionKind::RunCommand(cmd) | ActionKind::RunCommandOnlyOutput(cmd)) => {
+                        cmd
+                    }
+                    _ => {
+                        self.report(Diagnostic::new(
+                            line_no,
+                            line,
+                            span,
+                            ErrorKind::ExpectWithoutCommand,
+                        ));
+                        continue;
                     }
-                    Debug::fmt(&args[i], f)?;
+                };
+                match parse_expect(expectation, &self.variables) {
+                    Ok(expectation) => cmd.expect.push(expectation),
+                    Err(CommandError { span, kind }) => self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        offset + span.start..offset + span.end,
+                        kind,
+                    )),
                 }
-                Ok(())
+                continue;
             }
-            Self::RunCommandOnlyOutput(args) => {
-                f.write_char('-')?;
-                for i in 0..args.len() {
-                    if i != 0 {
-                        f.write_char(' ')?;
+
+            if let Some(target) = content
+                .strip_prefix("include")
+                .filter(|rest| rest.is_empty() || rest.starts_with(' '))
+            {
+                let included = self.include(path, line_no, line, indent + 7, target);
+                let mut included = included.into_iter();
+                // a note in front of an `include` belongs to its first action
+                if let Some(mut first) = included.next() {
+                    if let Some((_, note)) = note.take() {
+                        first.note = Some(match first.note {
+                            Some(inner) => format!("{note}\n{inner}").into_boxed_str(),
+                            None => note.into_boxed_str(),
+                        });
                     }
-                    Debug::fmt(&args[i], f)?;
+                    actions.push(first);
                 }
-                Ok(())
+                actions.extend(included);
+                continue;
             }
+
+            let mut parsed = parse_line(line_no, line, indent, &self.variables);
+            if let Ok(Some(ActionKind::RunCommandWithInput(_, heredoc))) = &mut parsed {
+                match self.read_heredoc(&lines, next, heredoc) {
+                    Some(end) => next = end + 1,
+                    None => {
+                        parsed = Err(Diagnostic::new(
+                            line_no,
+                            line,
+                            indent..line.trim_end().len(),
+                            ErrorKind::UnterminatedHeredoc(heredoc.delimiter.clone()),
+                        ));
+                        next = lines.len();
+                    }
+                }
+            }
+
+            if let Ok(Some(ActionKind::RunEditor(_, _, mode, _))) = &mut parsed {
+                *mode = self.edit_mode;
+            }
+            if let Ok(Some(kind)) = &mut parsed {
+                if let Some(cmd) = kind.command_line_mut() {
+                    cmd.shell = shell.take().unwrap_or_else(|| self.shell.clone());
+                    cmd.pacing = self.pacing;
+                }
+                let (timeout, name) = (timeout.take(), background_name.take());
+                self.apply_background(kind, timeout, name, line_no, line, indent);
+            }
+
+            if let Ok(Some(kind)) = parsed {
+                parsed = match (kind, fake_output.take()) {
+                    (ActionKind::RunCommand(cmd), Some(output)) => Ok(Some(
+                        ActionKind::FakeCommand(cmd, output, self.output_delay),
+                    )),
+                    (_, Some(_)) => Err(Diagnostic::new(
+                        line_no,
+                        line,
+                        indent..line.trim_end().len(),
+                        ErrorKind::MisplacedAnnotation("output"),
+                    )),
+                    (kind, None) => Ok(Some(kind)),
+                };
+            }
+
+            if let Ok(Some(kind)) = &parsed {
+                let replayed = matches!(
+                    kind,
+                    ActionKind::RunCommand(_)
+                        | ActionKind::RunCommandQuiet(_)
+                        | ActionKind::RunCommandOnlyOutput(_)
+                        | ActionKind::RunCommandWithInput(..)
+                );
+                if live && !replayed {
+                    live = false;
+                    parsed = Err(Diagnostic::new(
+                        line_no,
+                        line,
+                        indent..line.trim_end().len(),
+                        ErrorKind::MisplacedAnnotation("live"),
+                    ));
+                }
+            }
+
+            match parsed {
+                Ok(Some(kind)) => actions.push(Action {
+                    kind,
+                    line: line_no + 1,
+                    note: note.take().map(|(_, note)| note.into_boxed_str()),
+                    on_error: on_error.take().unwrap_or(self.on_error),
+                    show_status: self.show_status,
+                    live: std::mem::take(&mut live),
+                }),
+                Ok(None) => (),
+                Err(diagnostic) => self.report(diagnostic),
+            }
+        }
+
+        if let Some((line_no, _)) = note {
+            let line = contents.lines().nth(line_no).unwrap_or_default();
+            self.report(Diagnostic::new(
+                line_no,
+                line,
+                0..line.trim_end().len(),
+                ErrorKind::DanglingNote,
+            ));
         }
+
+        self.files.pop();
+        actions
     }
-}
 
-pub fn parse_actions(contents: String) -> Vec<Action> {
-    let mut actions = Vec::new();
+    /// Applies `@timeout` and `@name` to the action, and makes sure background
+    /// processes are started before they are killed or waited for.
+    fn apply_background(
+        &mut self,
+        kind: &mut ActionKind,
+        timeout: Option<Duration>,
+        name: Option<Box<str>>,
+        line_no: usize,
+        line: &str,
+        indent: usize,
+    ) {
+        let span = indent..line.trim_end().len();
+        if let Some(duration) = timeout {
+            match &mut *kind {
+                ActionKind::WaitBackground(_, timeout) => *timeout = Some(duration),
+                ActionKind::WaitFor(_, timeout) => *timeout = duration,
+                kind => match kind.command_line_mut() {
+                    Some(cmd) => cmd.timeout = Some(duration),
+                    None => self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        span.clone(),
+                        ErrorKind::MisplacedAnnotation("timeout"),
+                    )),
+                },
+            }
+        }
+        if let Some(custom) = name {
+            match &mut *kind {
+                ActionKind::RunBackground(name, _) => *name = custom,
+                _ => self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    span.clone(),
+                    ErrorKind::MisplacedAnnotation("name"),
+                )),
+            }
+        }
 
-    for line in contents.lines() {
-        let cmd = if line.starts_with('#') || line.starts_with('-') || line.starts_with('+') { parse_command(&line[1..]) } else { parse_command(line) };
-        if cmd.is_empty() {
-            continue;
+        match kind {
+            ActionKind::RunBackground(name, _) => _ = self.background.insert(name.clone()),
+            ActionKind::KillBackground(name)
+            | ActionKind::WaitBackground(name, _)
+            | ActionKind::WaitFor(Condition::Output(name, _), _)
+                if !self.background.contains(name) =>
+            {
+                let start = indent + line[indent..].find('&').unwrap_or(0);
+                self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    start..span.end,
+                    ErrorKind::UnknownBackground(name.clone()),
+                ));
+            }
+            _ => (),
         }
-        if cmd.len() == 2 && &*cmd[0] == "cd" && !line.starts_with('+') {
-            if line.starts_with('-') || line.starts_with('#') {
-                actions.push(Action::ChangeDirQuiet(cmd[1].clone()))
+    }
+
+    /// Applies `set <name> <value>`, where `words` are the parsed words of `setting`.
+    fn apply_setting(&mut self, setting: &str, words: &[Box<str>]) -> Result<(), CommandError> {
+        let span = 0..setting.trim_end().len();
+        let [name, value] = words else {
+            return Err(CommandError {
+                span,
+                kind: ErrorKind::InvalidSet,
+            });
+        };
+
+        match &**name {
+            "shell" => {
+                self.shell = match &**value {
+                    "on" => Some(default_shell()),
+                    "off" => None,
+                    shell => Some(shell.into()),
+                }
+            }
+            "on_error" => {
+                self.on_error = OnError::parse(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidSettingValue(value.clone(), ON_ERROR_VALUES),
+                })?
+            }
+            "output_delay" => {
+                self.output_delay = parse_duration(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidDuration(value.clone()),
+                })?
+            }
+            "output_rate" => {
+                self.pacing.rate = parse_limit(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidSettingValue(
+                        value.clone(),
+                        "a number of lines per second or `off`",
+                    ),
+                })?
+            }
+            "output_lines" => {
+                self.pacing.max_lines = parse_limit(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidSettingValue(
+                        value.clone(),
+                        "a number of lines or `off`",
+                    ),
+                })?
+            }
+            "edit" => {
+                self.edit_mode = EditMode::parse(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidSettingValue(value.clone(), "`retype` or `diff`"),
+                })?
+            }
+            "status" => {
+                self.show_status = ShowStatus::parse(value).ok_or_else(|| CommandError {
+                    span,
+                    kind: ErrorKind::InvalidSettingValue(value.clone(), "`off`, `code` or `mark`"),
+                })?
+            }
+            _ => {
+                return Err(CommandError {
+                    span,
+                    kind: ErrorKind::UnknownSetting(name.clone()),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the lines of a heredoc starting at `start` into `heredoc`, substituting
+    /// variables. Returns the index of the line with the delimiter, if there is one.
+    fn read_heredoc(
+        &mut self,
+        lines: &[&str],
+        start: usize,
+        heredoc: &mut Heredoc,
+    ) -> Option<usize> {
+        let strip_tabs = heredoc.delimiter.starts_with('-');
+        let delimiter = heredoc.delimiter.trim_start_matches('-');
+
+        let mut body = String::new();
+        for (line_no, line) in lines.iter().enumerate().skip(start) {
+            let text = if strip_tabs {
+                line.trim_start_matches('\t')
             } else {
-                actions.push(Action::ChangeDir(cmd[1].clone()))
+                line
+            };
+            if text.trim_end() == delimiter {
+                heredoc.delimiter = delimiter.into();
+                heredoc.body = body.into_boxed_str();
+                return Some(line_no);
             }
-            continue;
+
+            match substitute(text, &self.variables) {
+                Ok(text) => body.push_str(&text),
+                Err(CommandError { span, kind }) => {
+                    let offset = line.len() - text.len();
+                    self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        offset + span.start..offset + span.end,
+                        kind,
+                    ));
+                }
+            }
+            body.push('\n');
+        }
+        None
+    }
+
+    /// Reads the output of `@output <value>`, with `value` starting at byte
+    /// `value_start` of `line`. It is either a heredoc on the following lines, in
+    /// which case `next` is moved past it, or a file relative to the scene.
+    fn read_output(
+        &mut self,
+        path: &Path,
+        lines: &[&str],
+        next: &mut usize,
+        line_no: usize,
+        line: &str,
+        value_start: usize,
+    ) -> Option<Box<str>> {
+        let span = value_start..line.trim_end().len();
+        let words = match parse_command(&line[value_start..], &self.variables) {
+            Ok(words) => words.args,
+            Err(CommandError { span, kind }) => {
+                self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    value_start + span.start..value_start + span.end,
+                    kind,
+                ));
+                return None;
+            }
+        };
+
+        match heredoc_operator(&words) {
+            Some((operator_words, delimiter)) if operator_words == words.len() => {
+                let mut heredoc = Heredoc {
+                    delimiter,
+                    body: "".into(),
+                };
+                match self.read_heredoc(lines, *next, &mut heredoc) {
+                    Some(end) => {
+                        *next = end + 1;
+                        Some(heredoc.body)
+                    }
+                    None => {
+                        self.report(Diagnostic::new(
+                            line_no,
+                            line,
+                            span,
+                            ErrorKind::UnterminatedHeredoc(heredoc.delimiter),
+                        ));
+                        *next = lines.len();
+                        None
+                    }
+                }
+            }
+            _ => {
+                let [file] = &*words else {
+                    self.report(Diagnostic::new(
+                        line_no,
+                        line,
+                        span,
+                        ErrorKind::InvalidOutput,
+                    ));
+                    return None;
+                };
+                let file = path.parent().unwrap_or(Path::new("")).join(&**file);
+                match read_to_string(&file) {
+                    Ok(output) => Some(output.into_boxed_str()),
+                    Err(e) => {
+                        self.report(Diagnostic::new(
+                            line_no,
+                            line,
+                            span,
+                            ErrorKind::ReadFailed(file, e.to_string().into_boxed_str()),
+                        ));
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses the scene named by `include <target>`, found at byte `offset` of `line`.
+    fn include(
+        &mut self,
+        path: &Path,
+        line_no: usize,
+        line: &str,
+        offset: usize,
+        target: &str,
+    ) -> Vec<Action> {
+        let span = offset + target.len() - target.trim_start().len()..line.trim_end().len();
+        let words = match parse_command(target, &self.variables) {
+            Ok(words) => words.args,
+            Err(CommandError { span, kind }) => {
+                self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    offset + span.start..offset + span.end,
+                    kind,
+                ));
+                return vec![];
+            }
+        };
+        if words.len() != 1 {
+            self.report(Diagnostic::new(
+                line_no,
+                line,
+                span,
+                ErrorKind::InvalidInclude,
+            ));
+            return vec![];
+        }
+
+        let included = path.parent().unwrap_or(Path::new("")).join(&*words[0]);
+        let canonical = match included.canonicalize() {
+            Ok(v) => v,
+            Err(e) => {
+                self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    span,
+                    ErrorKind::IncludeFailed(included.into(), e.to_string().into()),
+                ));
+                return vec![];
+            }
+        };
+        if let Some(index) = self.files.iter().position(|(_, c)| *c == canonical) {
+            let mut cycle = self.files[index..]
+                .iter()
+                .map(|(path, _)| path.display().to_string())
+                .collect::<Vec<_>>();
+            cycle.push(included.display().to_string());
+            self.report(Diagnostic::new(
+                line_no,
+                line,
+                span,
+                ErrorKind::IncludeCycle(cycle.join(" -> ").into()),
+            ));
+            return vec![];
+        }
+        let contents = match read_to_string(&canonical) {
+            Ok(v) => v,
+            Err(e) => {
+                self.report(Diagnostic::new(
+                    line_no,
+                    line,
+                    span,
+                    ErrorKind::IncludeFailed(included.into(), e.to_string().into()),
+                ));
+                return vec![];
+            }
+        };
+
+        self.include_lines.push(line_no);
+        let actions = self.parse_file(&included, &contents);
+        self.include_lines.pop();
+        actions
+    }
+}
+
+/// Parses a line that isn't a comment or annotation. `indent` is the byte length
+/// of the leading whitespace. Blank lines and bare prefixes produce no action.
+fn parse_line(
+    line_no: usize,
+    line: &str,
+    indent: usize,
+    variables: &Variables,
+) -> Result<Option<ActionKind>, Diagnostic> {
+    let prefix = match line[indent..].chars().next() {
+        Some(c @ ('#' | '-' | '+' | '&')) => Some(c),
+        Some(c @ ('$' | '>')) if line[indent + 1..].starts_with([' ', '\t']) => {
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                indent..indent + 1,
+                ErrorKind::PastedPrompt(c),
+            ));
         }
+        Some(c) if is_unknown_prefix(c) => {
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                indent..indent + c.len_utf8(),
+                ErrorKind::UnknownPrefix(c),
+            ));
+        }
+        _ => None,
+    };
+    let offset = indent + if prefix.is_some() { 1 } else { 0 };
+
+    let cmd = match parse_command(&line[offset..], variables) {
+        Ok(cmd) => cmd,
+        Err(CommandError { span, kind }) => {
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                offset + span.start..offset + span.end,
+                kind,
+            ));
+        }
+    };
+    if let Some((_, delimiter)) = heredoc_operator(&cmd.args) {
+        if prefix.is_some() {
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                indent..indent + 1,
+                ErrorKind::HiddenHeredoc,
+            ));
+        }
+        let heredoc = Heredoc {
+            delimiter,
+            body: "".into(),
+        };
+        return Ok(Some(ActionKind::RunCommandWithInput(cmd, heredoc)));
+    }
 
-        if line.starts_with('#') {
-            actions.push(Action::RunCommandQuiet(cmd.into_boxed_slice()));
-        } else if line.starts_with('-') {
-            actions.push(Action::RunCommandOnlyOutput(cmd.into_boxed_slice()));
-        } else if line.starts_with('+') {
-            if cmd.len() != 2 {
-                panic!("Editor Command `+` expected 2 arguments: A source file and a destination file");
+    let CommandLine {
+        args: cmd,
+        text,
+        words,
+        script,
+        shell,
+        expect,
+        timeout,
+        pacing,
+    } = cmd;
+    if cmd.is_empty() {
+        if prefix == Some('+') {
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                indent..line.trim_end().len(),
+                ErrorKind::EditorArgumentCount(0),
+            ));
+        }
+        return Ok(None);
+    }
+    if &*cmd[0] == "cd" && prefix != Some('+') {
+        if cmd.len() == 1 {
+            let start = line.find("cd").unwrap_or(0);
+            return Err(Diagnostic::new(
+                line_no,
+                line,
+                start..start + 2,
+                ErrorKind::EmptyCd,
+            ));
+        }
+        if cmd.len() == 2 {
+            if prefix.is_some() {
+                return Ok(Some(ActionKind::ChangeDirQuiet(cmd[1].clone())));
             }
-            actions.push(Action::RunEditor(cmd[0].clone(), cmd[1].clone()));
-        } else {
-            actions.push(Action::RunCommand(cmd.into_boxed_slice()));
+            return Ok(Some(ActionKind::ChangeDir(cmd[1].clone(), text)));
+        }
+    }
+
+    if let (None, "kill" | "wait", [name]) = (prefix, &*cmd[0], &cmd[1..]) {
+        if let Some(name) = name.strip_prefix('&') {
+            let name = name.into();
+            return Ok(Some(match &*cmd[0] {
+                "kill" => ActionKind::KillBackground(name),
+                _ => ActionKind::WaitBackground(name, None),
+            }));
+        }
+    }
+    if let (None, "wait") = (prefix, &*cmd[0]) {
+        match parse_condition(&cmd[1..]) {
+            Some(Ok(condition)) => {
+                return Ok(Some(ActionKind::WaitFor(condition, WAIT_TIMEOUT)));
+            }
+            Some(Err(kind)) => {
+                return Err(Diagnostic::new(
+                    line_no,
+                    line,
+                    indent..line.trim_end().len(),
+                    kind,
+                ));
+            }
+            // a plain `wait` command
+            None => (),
+        }
+    }
+
+    let cmd = CommandLine {
+        args: cmd,
+        text,
+        words,
+        script,
+        shell,
+        expect,
+        timeout,
+        pacing,
+    };
+    let kind = match prefix {
+        Some('#') => ActionKind::RunCommandQuiet(cmd),
+        Some('&') => {
+            // named after the program unless there is a `@name`
+            let program = cmd.args[0].rsplit('/').next().unwrap_or_default();
+            ActionKind::RunBackground(program.into(), cmd)
+        }
+        Some('-') => ActionKind::RunCommandOnlyOutput(cmd),
+        Some('+') => {
+            if cmd.args.len() != 2 {
+                return Err(Diagnostic::new(
+                    line_no,
+                    line,
+                    indent..line.trim_end().len(),
+                    ErrorKind::EditorArgumentCount(cmd.args.len()),
+                ));
+            }
+            ActionKind::RunEditor(
+                cmd.args[0].clone(),
+                cmd.args[1].clone(),
+                EditMode::Retype,
+                cmd.words[0].clone(),
+            )
+        }
+        _ => ActionKind::RunCommand(cmd),
+    };
+    Ok(Some(kind))
+}
+
+/// Parses the words of `redact <rule>`.
+fn parse_redaction(rule: &str, words: &[Box<str>]) -> Result<Option<Redaction>, CommandError> {
+    let span = 0..rule.trim_end().len();
+    let words = words.iter().map(|word| &**word).collect::<Vec<_>>();
+    match &*words {
+        ["home"] => Ok(Redaction::home()),
+        ["keys"] => Ok(Some(Redaction::keys())),
+        ["username", replacement] => Ok(Redaction::username(replacement)),
+        ["hostname", replacement] => Ok(Redaction::hostname(replacement)),
+        ["regex", regex, replacement] => match Redaction::regex(regex, replacement) {
+            Ok(redaction) => Ok(Some(redaction)),
+            Err(e) => Err(CommandError {
+                span,
+                kind: ErrorKind::InvalidRegex(regex_error(&e).into()),
+            }),
+        },
+        _ => Err(CommandError {
+            span,
+            kind: ErrorKind::InvalidRedact,
+        }),
+    }
+}
+
+/// The reason a regex doesn't compile, without the regex itself.
+fn regex_error(e: &regex::Error) -> String {
+    let e = e.to_string();
+    // the last line has the reason, the others repeat the regex
+    let reason = e.lines().last().unwrap_or_default();
+    reason.trim_start_matches("error: ").to_string()
+}
+
+/// Parses the arguments of `wait` into a condition, if they are one.
+fn parse_condition(args: &[Box<str>]) -> Option<Result<Condition, ErrorKind>> {
+    let (kind, rest) = match args {
+        [duration] if duration.starts_with(|c: char| c.is_ascii_digit()) => {
+            return parse_duration(duration).map(|duration| Ok(Condition::Duration(duration)));
+        }
+        [kind, rest @ ..] if matches!(&**kind, "file" | "port" | "output") => (&**kind, rest),
+        _ => return None,
+    };
+
+    Some(match (kind, rest) {
+        ("file", [path]) => Ok(Condition::File(path.clone())),
+        ("port", [port]) => port
+            .parse()
+            .map(Condition::Port)
+            .map_err(|_| ErrorKind::InvalidPort(port.clone())),
+        ("output", [name, pattern]) if name.starts_with('&') => match Regex::new(pattern) {
+            Ok(regex) => Ok(Condition::Output(name[1..].into(), regex)),
+            Err(e) => Err(ErrorKind::InvalidRegex(regex_error(&e).into())),
+        },
+        _ => Err(ErrorKind::InvalidWait),
+    })
+}
+
+/// The user's shell, or `/bin/sh` if it is unknown.
+fn default_shell() -> Box<str> {
+    env::var("SHELL")
+        .ok()
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| "/bin/sh".to_string())
+        .into_boxed_str()
+}
+
+/// Whether `line` ends in an unescaped `\`.
+fn is_continued(line: &str) -> bool {
+    line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
+/// Finds a `<<DELIM` or `<< DELIM` at the end of a command. Returns how many words
+/// it takes up and the delimiter, starting with `-` for `<<-DELIM`.
+fn heredoc_operator(args: &[Box<str>]) -> Option<(usize, Box<str>)> {
+    let last = args.last()?;
+    if let Some(delimiter) = last.strip_prefix("<<") {
+        if !delimiter.is_empty() && delimiter != "-" && !delimiter.starts_with('<') {
+            return Some((1, delimiter.into()));
+        }
+    }
+    match args.len().checked_sub(2).map(|i| &*args[i]) {
+        Some("<<") => Some((2, last.clone())),
+        Some("<<-") => Some((2, format!("-{last}").into_boxed_str())),
+        _ => None,
+    }
+}
+
+/// Symbols that can't start a command and aren't one of the action prefixes, so
+/// they can only be a mistyped prefix. Shell syntax like `[ -f x ]`, `! grep` or
+/// `: > log` starts a command.
+fn is_unknown_prefix(c: char) -> bool {
+    matches!(c, '|' | ';' | ')' | '}')
+}
+
+/// Parses the part of `let name = value` after `let`.
+fn parse_let(
+    declaration: &str,
+    variables: &Variables,
+) -> Result<(Box<str>, Box<str>), CommandError> {
+    let invalid = || CommandError {
+        span: 0..declaration.trim_end().len(),
+        kind: ErrorKind::InvalidLet,
+    };
+
+    let Some((name, value)) = declaration.split_once('=') else {
+        return Err(invalid());
+    };
+    let name = name.trim();
+    let is_identifier = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_identifier {
+        return Err(invalid());
+    }
+
+    let value_offset = declaration.len() - value.len();
+    let words = parse_command(value, variables).map_err(|e| CommandError {
+        span: value_offset + e.span.start..value_offset + e.span.end,
+        kind: e.kind,
+    })?;
+    match &*words.args {
+        [value] => Ok((name.into(), value.clone())),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses the part of `expect "<prompt>" send "<answer>" [timeout <duration>]`
+/// after `expect`.
+fn parse_expect(expectation: &str, variables: &Variables) -> Result<Expectation, CommandError> {
+    let words = parse_command(expectation, variables)?.args;
+    let (prompt, answer, timeout) = match &*words {
+        [prompt, send, answer] if &**send == "send" => (prompt, answer, None),
+        [prompt, send, answer, timeout, duration]
+            if &**send == "send" && &**timeout == "timeout" =>
+        {
+            (prompt, answer, Some(duration))
         }
+        _ => {
+            return Err(CommandError {
+                span: 0..expectation.trim_end().len(),
+                kind: ErrorKind::InvalidExpect,
+            })
+        }
+    };
+    let timeout = match timeout {
+        Some(duration) => parse_duration(duration).ok_or_else(|| CommandError {
+            span: 0..expectation.trim_end().len(),
+            kind: ErrorKind::InvalidDuration(duration.clone()),
+        })?,
+        None => EXPECT_TIMEOUT,
+    };
+    if prompt.is_empty() {
+        return Err(CommandError {
+            span: 0..expectation.trim_end().len(),
+            kind: ErrorKind::InvalidExpect,
+        });
+    }
+
+    Ok(Expectation {
+        prompt: prompt.clone(),
+        answer: answer.clone(),
+        timeout,
+    })
+}
+
+/// Parses durations like `800ms`, `30s` or `2m`.
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let split = duration.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+    let (amount, unit) = duration.split_at(split);
+    let amount = amount.parse::<f64>().ok()?;
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "s" => amount,
+        "m" => amount * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses a positive number, or `off` for no limit.
+fn parse_limit<T: FromStr + PartialOrd + Default>(value: &str) -> Option<Option<T>> {
+    if value == "off" {
+        return Some(None);
+    }
+    let limit = value.parse().ok()?;
+    (limit > T::default()).then_some(Some(limit))
+}
+
+/// A problem in a single command line; `span` is a byte range into it.
+struct CommandError {
+    span: Range<usize>,
+    kind: ErrorKind,
+}
+
+/// Substitutes the `${name}` starting at byte `start` of `text`. Returns the value
+/// and the byte offset right after the closing `}`.
+fn expand_variable(
+    text: &str,
+    start: usize,
+    variables: &Variables,
+) -> Result<(Box<str>, usize), CommandError> {
+    let Some(len) = text[start + 2..].find('}') else {
+        return Err(CommandError {
+            span: start..text.trim_end().len(),
+            kind: ErrorKind::UnterminatedVariable,
+        });
+    };
+    let name = &text[start + 2..start + 2 + len];
+    let end = start + 2 + len + 1;
+    match variables.get(name) {
+        Some(value) => Ok((value, end)),
+        None => Err(CommandError {
+            span: start..end,
+            kind: ErrorKind::UndefinedVariable(name.into()),
+        }),
+    }
+}
+
+/// Substitutes every `${name}` in `text`, keeping everything else as-is.
+fn substitute(text: &str, variables: &Variables) -> Result<String, CommandError> {
+    let mut substituted = String::new();
+    let mut rest = 0;
+    while let Some(i) = text[rest..].find("${").map(|i| rest + i) {
+        if text[..i].ends_with('\\') {
+            substituted.push_str(&text[rest..i - 1]);
+            substituted.push_str("${");
+            rest = i + 2;
+            continue;
+        }
+        let (value, end) = expand_variable(text, i, variables)?;
+        substituted.push_str(&text[rest..i]);
+        substituted.push_str(&value);
+        rest = end;
     }
+    substituted.push_str(&text[rest..]);
+    Ok(substituted)
+}
 
-    return actions;
+/// Quotes `value` for a POSIX shell, inside double quotes if `quoted`.
+fn shell_quote(value: &str, quoted: bool) -> String {
+    match quoted {
+        true => value.chars().fold(String::new(), |mut quoted, c| {
+            if matches!(c, '$' | '`' | '"' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+            quoted
+        }),
+        false => format!("'{}'", value.replace('\'', r"'\''")),
+    }
 }
 
-fn parse_command(cmd: &str) -> Vec<Box<str>> {
+/// Splits a command line into words the way a POSIX shell does, substituting
+/// `${name}` outside of single quotes. Unquoted `\` escapes any character,
+/// inside double quotes it only escapes `$`, `` ` ``, `"`, `\` and newlines.
+fn parse_command(cmd: &str, variables: &Variables) -> Result<CommandLine, CommandError> {
     let mut command_list = vec![];
     let mut last = String::new();
+    // whether `last` is a word, even if it is empty, like `""`
+    let mut in_word = false;
+    let mut text = String::new();
+    let mut script = String::new();
+    let mut words = vec![];
+    // where the word that is or might be starting is written in `text`
+    let mut word_text_start = 0;
 
     let mut escape = false;
-    let mut in_str = false;
+    // the quote character and where the quote started
+    let mut quote: Option<(char, usize)> = None;
+    let mut word_start = true;
+    // end of a `${name}` that was already substituted
+    let mut skip_until = 0;
+
+    for (i, c) in cmd.char_indices() {
+        if i < skip_until {
+            continue;
+        }
+        if c == '#' && word_start && quote.is_none() {
+            // a `#` starting a word comments out the rest of the line
+            break;
+        }
+        word_start = matches!(c, ' ' | '\t') && !escape && quote.is_none();
+        if !in_word && !escape {
+            word_text_start = text.len();
+        }
 
-    for c in cmd.chars() {
         if escape {
-            last.push(c);
-        } else if c == '\\' {
-            escape = true;
-        } else if in_str {
-            if c == '"' {
-                in_str = false;
-                command_list.push(std::mem::take(&mut last).into_boxed_str());
-            } else {
+            escape = false;
+            text.push(c);
+            script.push(c);
+            match (c, quote) {
+                // a line continuation is removed and separates words outside of quotes
+                ('\n', None) => {
+                    if in_word {
+                        command_list.push(std::mem::take(&mut last).into_boxed_str());
+                        // without the `\` line continuation
+                        words.push(text[word_text_start..text.len() - 2].into());
+                        in_word = false;
+                    }
+                    word_start = true;
+                }
+                ('\n', Some(_)) => (),
+                ('$' | '`' | '"' | '\\', Some(_)) | (_, None) => {
+                    last.push(c);
+                    in_word = true;
+                }
+                (c, Some(_)) => {
+                    last.push('\\');
+                    last.push(c);
+                }
+            }
+            continue;
+        }
+
+        match (c, quote) {
+            ('\'', Some(('\'', _))) | ('"', Some(('"', _))) => {
+                quote = None;
+                text.push(c);
+                script.push(c);
+            }
+            (c, Some(('\'', _))) => {
                 last.push(c);
+                text.push(c);
+                script.push(c);
             }
-        } else if c == '"' {
-            in_str = true;
-        } else if c == ' ' {
-            if !last.is_empty() {
-                command_list.push(std::mem::take(&mut last).into_boxed_str());
+            ('$', _) if cmd[i + 1..].starts_with('{') => {
+                let (value, end) = expand_variable(cmd, i, variables)?;
+                last.push_str(&value);
+                text.push_str(&value);
+                script.push_str(&shell_quote(&value, quote.is_some()));
+                in_word = true;
+                skip_until = end;
+            }
+            ('\\', _) => {
+                escape = true;
+                // `\${` only keeps `${` from being substituted, a shell gets `${`
+                if !cmd[i + 1..].starts_with("${") {
+                    text.push(c);
+                    script.push(c);
+                }
+            }
+            ('\'' | '"', None) => {
+                quote = Some((c, i));
+                in_word = true;
+                text.push(c);
+                script.push(c);
+            }
+            (' ' | '\t', None) => {
+                if in_word {
+                    command_list.push(std::mem::take(&mut last).into_boxed_str());
+                    words.push(text[word_text_start..].into());
+                    in_word = false;
+                }
+                text.push(c);
+                script.push(c);
+            }
+            (c, _) => {
+                last.push(c);
+                in_word = true;
+                text.push(c);
+                script.push(c);
             }
-        } else {
-            last.push(c);
         }
     }
 
-    if in_str {
-        last.insert(0, '"');
+    if let Some((_, start)) = quote {
+        return Err(CommandError {
+            span: start..cmd.trim_end().len(),
+            kind: ErrorKind::UnterminatedQuote,
+        });
     }
     if escape {
         last.push('\\');
+        in_word = true;
+    }
+    if in_word {
+        command_list.push(last.into_boxed_str());
+        words.push(text[word_text_start..].into());
+    }
+
+    Ok(CommandLine {
+        args: command_list.into_boxed_slice(),
+        text: text.trim().into(),
+        words: words.into_boxed_slice(),
+        script: script.trim().into(),
+        shell: None,
+        expect: vec![],
+        timeout: None,
+        pacing: Pacing::default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{parse_command, CommandLine, Variables};
+    use crate::error::ErrorKind;
+
+    fn parse(cmd: &str) -> CommandLine {
+        let overrides = HashMap::from([
+            ("name".into(), "my app".into()),
+            ("quote".into(), "it's $HOME".into()),
+        ]);
+        match parse_command(cmd, &Variables::new(&overrides)) {
+            Ok(cmd) => cmd,
+            Err(e) => panic!("{cmd:?} failed to parse: {}", e.kind),
+        }
     }
-    if !last.is_empty() {
-        command_list.push(std::mem::take(&mut last).into_boxed_str());
+
+    fn args(cmd: &str) -> Vec<String> {
+        parse(cmd).args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn error(cmd: &str) -> ErrorKind {
+        let overrides = HashMap::new();
+        match parse_command(cmd, &Variables::new(&overrides)) {
+            Ok(_) => panic!("{cmd:?} parsed"),
+            Err(e) => e.kind,
+        }
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(args("cargo  init\t. "), ["cargo", "init", "."]);
+        assert!(args("   ").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_and_escaped_whitespace() {
+        assert_eq!(args(r#"'a b' "c d" e\ f"#), ["a b", "c d", "e f"]);
+    }
+
+    #[test]
+    fn joins_adjacent_quotes_and_keeps_empty_arguments() {
+        assert_eq!(args(r#"foo"bar"'baz' """#), ["foobarbaz", ""]);
     }
 
-    command_list
-}
\ No newline at end of file
+    #[test]
+    fn escapes_only_some_characters_in_double_quotes() {
+        assert_eq!(args(r#"echo "a\"b\$c\n\\""#), ["echo", r#"a"b$c\n\"#]);
+        assert_eq!(args(r"echo 'a\b'"), ["echo", r"a\b"]);
+    }
+
+    #[test]
+    fn ends_at_a_comment() {
+        let cmd = parse("echo a#b # comment");
+        assert_eq!(&*cmd.args, ["echo".into(), "a#b".into()]);
+        assert_eq!(&*cmd.text, "echo a#b");
+    }
+
+    #[test]
+    fn removes_line_continuations() {
+        assert_eq!(args("echo a\\\nb \"c\\\nd\""), ["echo", "a", "b", "cd"]);
+    }
+
+    #[test]
+    fn keeps_how_words_are_written() {
+        let cmd = parse(r#"edit "a b" c\ d"#);
+        let words: Vec<_> = cmd.words.iter().map(|word| word.to_string()).collect();
+        assert_eq!(words, ["edit", r#""a b""#, r"c\ d"]);
+    }
+
+    #[test]
+    fn substitutes_variables_outside_of_single_quotes() {
+        let cmd = r#"cd ${name} "${name}" '${name}'"#;
+        assert_eq!(args(cmd), ["cd", "my app", "my app", "${name}"]);
+        assert_eq!(&*parse(cmd).text, r#"cd my app "my app" '${name}'"#);
+    }
+
+    #[test]
+    fn keeps_escaped_variables() {
+        let cmd = parse(r"echo \${name}");
+        assert_eq!(&*cmd.args, ["echo".into(), "${name}".into()]);
+        assert_eq!(&*cmd.text, "echo ${name}");
+        assert_eq!(&*cmd.script, "echo ${name}");
+    }
+
+    #[test]
+    fn quotes_substituted_values_for_a_shell() {
+        assert_eq!(&*parse("echo ${quote}").script, r"echo 'it'\''s $HOME'");
+        assert_eq!(&*parse(r#"ls "${quote}""#).script, r#"ls "it's \$HOME""#);
+        assert_eq!(&*parse("echo ${quote}").text, "echo it's $HOME");
+    }
+
+    #[test]
+    fn reports_unterminated_quotes_and_variables() {
+        assert!(matches!(error(r#"echo "a"#), ErrorKind::UnterminatedQuote));
+        assert!(matches!(error("echo 'a"), ErrorKind::UnterminatedQuote));
+        assert!(matches!(error("echo ${a"), ErrorKind::UnterminatedVariable));
+    }
+
+    #[test]
+    fn reports_undefined_variables() {
+        match error("echo ${SCAMMED_UNDEFINED}") {
+            ErrorKind::UndefinedVariable(name) => assert_eq!(&*name, "SCAMMED_UNDEFINED"),
+            kind => panic!("unexpected error: {kind}"),
+        }
+    }
+}
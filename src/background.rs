@@ -0,0 +1,223 @@
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::actions::CommandLine;
+use crate::command::{build_command, timed_out, CommandOutput, Watchdog};
+use crate::sandbox;
+
+/// How many background processes can run at the same time.
+const MAX_PROCESSES: usize = 32;
+/// How long a killed background process gets to exit before it is killed forcefully.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Process groups of the running background processes and of the command running
+/// in the foreground, so a signal handler can stop them. Free slots are 0.
+static GROUPS: [AtomicI32; MAX_PROCESSES] = [const { AtomicI32::new(0) }; MAX_PROCESSES];
+
+struct Process {
+    name: Box<str>,
+    child: Child,
+    // index into `GROUPS`
+    slot: usize,
+    // stdout and stderr of the process so far
+    output: Arc<Mutex<Vec<u8>>>,
+    // kills the process if it has a timeout
+    watchdog: Watchdog,
+}
+
+/// The processes started with `&`. They run in their own process group, so
+/// everything they start is stopped along with them, which happens at the
+/// latest when this is dropped or scammed is interrupted.
+pub struct Background {
+    processes: Vec<Process>,
+}
+
+impl Background {
+    pub fn new() -> Self {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe {
+                libc::signal(
+                    signal,
+                    stop_all as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            };
+        }
+        Self { processes: vec![] }
+    }
+
+    /// Starts `cmd` without showing it or its output.
+    pub fn start(&mut self, name: &str, cmd: &CommandLine) -> io::Result<()> {
+        if self.processes.iter().any(|process| &*process.name == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a background process named `{name}` is already running"),
+            ));
+        }
+        let Some(slot) = GROUPS
+            .iter()
+            .position(|group| group.load(Ordering::SeqCst) == 0)
+        else {
+            return Err(io::Error::other(format!(
+                "can't run more than {MAX_PROCESSES} background processes"
+            )));
+        };
+
+        let mut command = build_command(cmd)?;
+        let (mut reader, writer) = io::pipe()?;
+        command.stdout(writer.try_clone()?);
+        command.stderr(writer);
+        command.stdin(Stdio::null());
+        command.process_group(0);
+        let child = command.spawn()?;
+        drop(command);
+        GROUPS[slot].store(child.id() as i32, Ordering::SeqCst);
+
+        let output = Arc::new(Mutex::new(vec![]));
+        let collected = output.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                collected.lock().unwrap().extend_from_slice(&buf[..n]);
+            }
+        });
+
+        self.processes.push(Process {
+            name: name.into(),
+            watchdog: Watchdog::start(-(child.id() as i32), cmd.timeout),
+            child,
+            slot,
+            output,
+        });
+        Ok(())
+    }
+
+    /// Stops the process and everything it started.
+    pub fn kill(&mut self, name: &str) -> io::Result<()> {
+        let process = self.remove(name)?;
+        stop(process);
+        Ok(())
+    }
+
+    /// Waits for the process to exit on its own, killing it if it takes longer
+    /// than `timeout`.
+    pub fn wait(&mut self, name: &str, timeout: Option<Duration>) -> io::Result<CommandOutput> {
+        let mut process = self.remove(name)?;
+        let group = -(process.child.id() as i32);
+        let watchdog = Watchdog::start(group, timeout);
+        let status = process.child.wait();
+        let killed = watchdog.finish();
+        let output = std::mem::take(&mut *process.output.lock().unwrap());
+        let timeout_killed = process.watchdog.finish();
+        GROUPS[process.slot].store(0, Ordering::SeqCst);
+
+        match timeout {
+            Some(timeout) if killed => Err(timed_out(timeout)),
+            _ if timeout_killed => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("background process `{name}` timed out"),
+            )),
+            _ => Ok(CommandOutput {
+                status: status?,
+                output,
+                timing: vec![],
+            }),
+        }
+    }
+
+    /// Everything the process printed so far and whether it exited already.
+    pub fn output(&mut self, name: &str) -> io::Result<(Vec<u8>, bool)> {
+        let Some(process) = self
+            .processes
+            .iter_mut()
+            .find(|process| &*process.name == name)
+        else {
+            return Err(not_running(name));
+        };
+        let exited = process.child.try_wait()?.is_some();
+        let output = process.output.lock().unwrap().clone();
+        Ok((output, exited))
+    }
+
+    fn remove(&mut self, name: &str) -> io::Result<Process> {
+        match self
+            .processes
+            .iter()
+            .position(|process| &*process.name == name)
+        {
+            Some(index) => Ok(self.processes.remove(index)),
+            None => Err(not_running(name)),
+        }
+    }
+}
+
+fn not_running(name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no background process named `{name}` is running"),
+    )
+}
+
+/// Registers the process group of a command running in the foreground with the
+/// signal handler while it lives. The command runs in a group of its own, which
+/// an interrupt from the terminal doesn't reach.
+pub struct ForegroundGroup(Option<usize>);
+
+impl ForegroundGroup {
+    pub fn register(group: u32) -> Self {
+        // not registered if the background processes took every slot
+        let slot = GROUPS.iter().position(|slot| {
+            slot.compare_exchange(0, group as i32, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        Self(slot)
+    }
+}
+
+impl Drop for ForegroundGroup {
+    fn drop(&mut self) {
+        if let Some(slot) = self.0 {
+            GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        for process in self.processes.drain(..) {
+            stop(process);
+        }
+    }
+}
+
+/// Asks the process group to terminate, killing it if it doesn't within the
+/// grace period.
+fn stop(mut process: Process) {
+    let group = -(process.child.id() as i32);
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    let watchdog = Watchdog::start(group, Some(KILL_GRACE_PERIOD));
+    _ = process.child.wait();
+    watchdog.finish();
+    process.watchdog.finish();
+    GROUPS[process.slot].store(0, Ordering::SeqCst);
+}
+
+/// Stops every background process and the command in the foreground, removes the
+/// sandbox and exits the way the signal would have.
+extern "C" fn stop_all(signal: libc::c_int) {
+    for group in &GROUPS {
+        let group = group.load(Ordering::SeqCst);
+        if group != 0 {
+            unsafe { libc::kill(-group, libc::SIGTERM) };
+        }
+    }
+    sandbox::remove_from_signal_handler();
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs::read_to_string;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+
+use anathema::prelude::{Document, Runtime, TuiBackend};
+use crossterm::style::{ContentStyle, Stylize};
+use syntect::highlighting::{Theme, ThemeSet};
+
+use crate::actions::{Action, ActionKind, EditMode};
+use crate::directive::DirectiveError;
+use crate::parse::Parser;
+use crate::{diff, resolve_path, syntax, THEME_PATH};
+
+struct Problem {
+    // index and rendering of the action the problem belongs to
+    action: Option<(usize, String)>,
+    message: String,
+}
+
+struct Checker {
+    // the working directory the current action would run in
+    cwd: PathBuf,
+    // the theme files are highlighted with, if it could be loaded
+    theme: Option<Theme>,
+    // directories the scene creates before they are needed
+    created: HashSet<PathBuf>,
+    problems: Vec<Problem>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self {
+            cwd: env::current_dir().expect("Failed to get current directory"),
+            theme: None,
+            created: HashSet::new(),
+            problems: vec![],
+        }
+    }
+
+    fn problem(&mut self, action: Option<(usize, &Action)>, message: String) {
+        self.problems.push(Problem {
+            action: action.map(|(index, action)| (index, action.to_string())),
+            message,
+        });
+    }
+
+    fn check_resources(&mut self) {
+        match ThemeSet::get_theme(THEME_PATH) {
+            Ok(theme) => self.theme = Some(theme),
+            Err(e) => self.problem(None, format!("failed to load theme {THEME_PATH}: {e}")),
+        }
+
+        let components = match Path::new("components").read_dir() {
+            Ok(v) => v,
+            Err(e) => {
+                self.problem(None, format!("failed to read components/: {e}"));
+                return;
+            }
+        };
+        for entry in components.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "aml") {
+                if let Err(e) = read_to_string(&path) {
+                    self.problem(None, format!("failed to load {}: {e}", path.display()));
+                }
+            }
+        }
+        self.check_templates();
+    }
+
+    /// Parses the templates by building the editor's runtime the way `main` does,
+    /// without running it.
+    fn check_templates(&mut self) {
+        let backend = match TuiBackend::builder().finish() {
+            Ok(v) => v,
+            Err(e) => {
+                self.problem(None, format!("failed to set up the editor's backend: {e}"));
+                return;
+            }
+        };
+        let mut runtime = Runtime::builder(Document::new("@main"), backend);
+        let components = [
+            ("main", "components/index.aml"),
+            ("footer", "components/footer.aml"),
+            ("folder_list", "components/folder_list.aml"),
+        ];
+        for (name, path) in components {
+            // the templates don't depend on the component's state
+            if let Err(e) = runtime.register_component(name, path, (), ()) {
+                self.problem(None, format!("failed to load {path}: {e}"));
+                return;
+            }
+        }
+        if let Err(e) = runtime.finish() {
+            self.problem(
+                None,
+                format!("failed to parse the templates in components/: {e}"),
+            );
+        }
+    }
+
+    fn check_action(&mut self, index: usize, action: &Action) {
+        match &action.kind {
+            ActionKind::ChangeDir(dir, _) | ActionKind::ChangeDirQuiet(dir) => {
+                let target = normalize(&resolve_path(&self.cwd, dir));
+                if !self.dir_exists(&target) {
+                    self.problem(
+                        Some((index, action)),
+                        format!(
+                            "directory {} does not exist and is not created by an earlier `mkdir`",
+                            target.display()
+                        ),
+                    );
+                }
+                self.cwd = target;
+            }
+            ActionKind::RunCommand(cmd)
+            | ActionKind::RunCommandQuiet(cmd)
+            | ActionKind::RunCommandOnlyOutput(cmd)
+            | ActionKind::RunCommandWithInput(cmd, _)
+            | ActionKind::RunBackground(_, cmd) => {
+                let Some(program) = cmd.args.first() else { return };
+                match &cmd.shell {
+                    // the command itself might be shell syntax, only the shell has to exist
+                    Some(shell) if !self.program_exists(shell) => self.problem(
+                        Some((index, action)),
+                        format!("shell `{shell}` could not be found"),
+                    ),
+                    None if !self.program_exists(program) => self.problem(
+                        Some((index, action)),
+                        format!("program `{program}` could not be found on PATH"),
+                    ),
+                    _ => (),
+                }
+                if &**program == "mkdir" {
+                    for dir in cmd.args[1..].iter().filter(|arg| !arg.starts_with('-')) {
+                        let dir = normalize(&self.cwd.join(&**dir));
+                        // `mkdir -p` creates every parent as well
+                        self.created.extend(dir.ancestors().map(Path::to_path_buf));
+                    }
+                }
+            }
+            ActionKind::RunEditor(dst, src, mode, _) => {
+                let src = normalize(&self.cwd.join(&**src));
+                if !src.is_file() {
+                    self.problem(
+                        Some((index, action)),
+                        format!("source file {} does not exist", src.display()),
+                    );
+                }
+
+                // the destination is what ends up being highlighted
+                match Path::new(&**dst).file_name().and_then(|name| name.to_str()) {
+                    None => self.problem(
+                        Some((index, action)),
+                        format!("{dst} is not a file name"),
+                    ),
+                    Some(name) if !syntax::supports_file(name) => self.problem(
+                        Some((index, action)),
+                        format!("no syntax highlighting available for {name}"),
+                    ),
+                    Some(name) => {
+                        if let Err(e) = self.check_directives(&src, name, *mode) {
+                            self.problem(Some((index, action)), format!("{}: {e}", src.display()));
+                        }
+                    }
+                }
+            }
+            ActionKind::KillBackground(_)
+            | ActionKind::WaitBackground(..)
+            | ActionKind::WaitFor(..)
+            // the program only has to exist on the machine the output came from
+            | ActionKind::FakeCommand(..) => (),
+        }
+    }
+
+    /// Goes through the directives of `src` the way the editor would.
+    fn check_directives(
+        &self,
+        src: &Path,
+        name: &str,
+        mode: EditMode,
+    ) -> Result<(), DirectiveError> {
+        let (Some(theme), Ok(code)) = (&self.theme, read_to_string(src)) else {
+            return Ok(());
+        };
+        let lines = syntax::highlight(&code, name, theme);
+        let comment = syntax::comment(name);
+        match mode {
+            EditMode::Retype => Parser::new(lines, comment).instructions().map(drop),
+            EditMode::Diff => diff::edit_instructions(Box::new([]), &lines, comment).map(drop),
+        }
+    }
+
+    fn dir_exists(&self, path: &Path) -> bool {
+        self.created.contains(path) || path.is_dir()
+    }
+
+    fn program_exists(&self, program: &str) -> bool {
+        if program.contains('/') {
+            return is_executable(&normalize(&resolve_path(&self.cwd, program)));
+        }
+
+        let Some(paths) = env::var_os("PATH") else {
+            return false;
+        };
+        env::split_paths(&paths).any(|dir| is_executable(&dir.join(program)))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Resolves `.` and `..` without touching the filesystem, as the directories
+/// might only be created while the scene runs.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Validates a scene without running any of its actions, printing a report.
+/// Returns the exit code for the process.
+pub fn check_scene(path: &str, actions: &[Action]) -> i32 {
+    let mut checker = Checker::new();
+    checker.check_resources();
+    for (index, action) in actions.iter().enumerate() {
+        checker.check_action(index, action);
+    }
+
+    let error = ContentStyle::default().red().bold().apply("error:");
+    for problem in &checker.problems {
+        match &problem.action {
+            Some((index, action)) => eprintln!(
+                "{error} action {} `{action}`: {}",
+                index + 1,
+                problem.message
+            ),
+            None => eprintln!("{error} {}", problem.message),
+        }
+    }
+
+    match checker.problems.len() {
+        0 => {
+            println!(
+                "{}",
+                ContentStyle::default().green().apply(format!(
+                    "{path}: {} actions, no problems found",
+                    actions.len()
+                ))
+            );
+            0
+        }
+        1 => {
+            eprintln!("{path}: found 1 problem");
+            1
+        }
+        n => {
+            eprintln!("{path}: found {n} problems");
+            1
+        }
+    }
+}
@@ -1,48 +1,208 @@
-use std::{io::Write, process::{Command, Stdio}};
+use std::io::{self, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-pub fn run_command(cmd: &[Box<str>]) -> std::io::Result<()> {
-    if cmd.len() < 1 {
-        return Ok(());
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+use crate::actions::{CommandLine, Heredoc};
+use crate::background::ForegroundGroup;
+use crate::pty::Pty;
+use crate::DEFAULT_SHELL;
+
+/// How long to keep collecting output once a command exited.
+const OUTPUT_LINGER: Duration = Duration::from_millis(100);
+
+/// How a command ended and what it printed.
+#[derive(Debug)]
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    // stdout and stderr, interleaved the way the command printed them
+    pub output: Vec<u8>,
+    // when each part of `output` was printed, as the time since the command
+    // started and the end of the part in `output`
+    pub timing: Vec<(Duration, usize)>,
+}
+
+/// Kills a process once its time is up, unless it finished before that.
+pub struct Watchdog(Option<(Sender<()>, JoinHandle<bool>)>);
+
+impl Watchdog {
+    /// Starts watching `pid`, which is a process group if it is negative.
+    pub fn start(pid: i32, timeout: Option<Duration>) -> Self {
+        let Some(timeout) = timeout else {
+            return Self(None);
+        };
+        let (done, finished) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let expired = finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+            if expired {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+            expired
+        });
+        Self(Some((done, handle)))
+    }
+
+    /// Stops watching. Returns whether the process was killed.
+    pub fn finish(self) -> bool {
+        let Some((done, handle)) = self.0 else {
+            return false;
+        };
+        _ = done.send(());
+        handle.join().unwrap_or(false)
     }
-    let mut args = cmd.iter().map(|el| &**el);
-    let Some(cmd) = args.next() else { return Ok(()); };
-    let mut cmd = Command::new(cmd);
-    cmd.args(args);
+}
+
+pub fn timed_out(timeout: Duration) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("timed out after {timeout:?}"),
+    )
+}
+
+/// Builds the process for `cmd`, either running its arguments directly or running
+/// its script with its shell.
+pub fn build_command(cmd: &CommandLine) -> io::Result<Command> {
+    let mut command = match &cmd.shell {
+        Some(shell) => {
+            let mut command = Command::new(&**shell);
+            command.arg("-c").arg(&*cmd.script);
+            command
+        }
+        None => {
+            let mut args = cmd.args.iter().map(|el| &**el);
+            let program = args
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+    };
     if let Ok(dir) = std::env::current_dir() {
-        cmd.current_dir(dir);
+        command.current_dir(dir);
     }
-    _ = std::io::stderr().flush();
-    _ = std::io::stdout().flush();
-    // todo: clear stdin?
+    Ok(command)
+}
+
+/// Throws away key presses that haven't been read yet, so they don't end up in
+/// a command or skip the next pause.
+pub fn discard_pending_input() {
+    // fails when stdin isn't a terminal, there is nothing pending to discard then
+    unsafe { libc::tcflush(libc::STDIN_FILENO, libc::TCIFLUSH) };
+}
 
-    cmd.stderr(Stdio::inherit());
-    cmd.stdout(Stdio::inherit());
-    cmd.stdin(Stdio::inherit());
-    // todo: clear stdin?
+fn flush_output() {
+    _ = io::stderr().flush();
+    _ = io::stdout().flush();
+}
 
-    let mut child = cmd.spawn()?;
-    while child.try_wait()?.is_none() {}
+/// Runs the command inside a pseudo-terminal, relaying its output and the user's
+/// key presses, so it behaves like it would when run in a terminal.
+pub fn run_command(cmd: &CommandLine) -> io::Result<CommandOutput> {
+    let mut command = build_command(cmd)?;
+    flush_output();
+    discard_pending_input();
 
-    Ok(())
+    let pty = Pty::open()?;
+    let mut child = pty.spawn(&mut command)?;
+
+    // the child is the leader of its own session, whatever it started goes as well
+    let watchdog = Watchdog::start(-(child.id() as i32), cmd.timeout);
+    let _group = ForegroundGroup::register(child.id());
+    _ = enable_raw_mode();
+    let output = pty.relay(&mut child, &cmd.expect, cmd.pacing);
+    _ = disable_raw_mode();
+    discard_pending_input();
+    match (cmd.timeout, watchdog.finish()) {
+        (Some(timeout), true) => Err(timed_out(timeout)),
+        _ => output,
+    }
+}
+
+/// Runs the command without showing its output.
+pub fn run_command_quiet(cmd: &CommandLine) -> io::Result<CommandOutput> {
+    let command = build_command(cmd)?;
+    flush_output();
+    discard_pending_input();
+
+    let output = capture(command, cmd.timeout);
+    discard_pending_input();
+    output
+}
+
+/// Runs the command with the heredoc's body as its stdin, showing its output.
+pub fn run_command_with_input(cmd: &CommandLine, heredoc: &Heredoc) -> io::Result<CommandOutput> {
+    run_command(&heredoc_script(cmd, heredoc))
 }
 
-pub fn run_command_quiet(cmd: &[Box<str>]) -> std::io::Result<()> {
-    if cmd.len() < 1 {
-        return Ok(());
+/// The command and the heredoc as a script for a shell, which reads the heredoc
+/// itself and handles redirections like `cat > file <<EOF` as well. Uses the
+/// command's shell, or `DEFAULT_SHELL` if it has none.
+fn heredoc_script(cmd: &CommandLine, heredoc: &Heredoc) -> CommandLine {
+    let script = format!("{}\n{}{}\n", cmd.script, heredoc.body, heredoc.delimiter);
+    CommandLine {
+        script: script.into_boxed_str(),
+        shell: Some(cmd.shell.clone().unwrap_or_else(|| DEFAULT_SHELL.into())),
+        ..cmd.clone()
     }
-    let mut args = cmd.iter().map(|el| &**el);
-    let Some(cmd) = args.next() else { return Ok(()); };
-    let mut cmd = Command::new(cmd);
-    cmd.args(args);
-    if let Ok(dir) = std::env::current_dir() {
-        cmd.current_dir(dir);
+}
+
+/// Runs `command` until it exits, collecting its stdout and stderr.
+fn capture(mut command: Command, timeout: Option<Duration>) -> io::Result<CommandOutput> {
+    // both ends of a single pipe, so the output stays in order
+    let (mut reader, writer) = io::pipe()?;
+    command.stdout(writer.try_clone()?);
+    command.stderr(writer);
+    command.stdin(Stdio::null());
+    // in a group of its own, so whatever it starts can be killed along with it
+    command.process_group(0);
+    let mut child = command.spawn()?;
+    // drop the copies of the pipe `command` holds on to, so reading ends with the child
+    drop(command);
+
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if tx.send((start.elapsed(), buf[..n].to_vec())).is_err() {
+                break;
+            }
+        }
+    });
+
+    let watchdog = Watchdog::start(-(child.id() as i32), timeout);
+    let _group = ForegroundGroup::register(child.id());
+    let status = child.wait();
+    if let (Some(timeout), true) = (timeout, watchdog.finish()) {
+        return Err(timed_out(timeout));
     }
-    _ = std::io::stderr().flush();
-    _ = std::io::stdout().flush();
-    // todo: clear stdin?
-    
-    let mut child = cmd.spawn()?;
-    while child.try_wait()?.is_none() {}
-
-    Ok(())
-}
\ No newline at end of file
+    let status = status?;
+    let mut output = vec![];
+    let mut timing = vec![];
+    // a process the command left running in the background might keep the pipe
+    // open, only wait a moment for output that is still on its way
+    let deadline = Instant::now() + OUTPUT_LINGER;
+    while let Ok((time, chunk)) =
+        rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    {
+        output.extend(chunk);
+        timing.push((time, output.len()));
+    }
+
+    Ok(CommandOutput {
+        status,
+        output,
+        timing,
+    })
+}
@@ -0,0 +1,62 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::actions::Condition;
+use crate::background::Background;
+
+/// How long to wait between checking a condition.
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Blocks until `condition` holds, failing if it doesn't within `timeout`.
+pub fn wait_for(
+    condition: &Condition,
+    timeout: Duration,
+    background: &mut Background,
+) -> io::Result<()> {
+    if let Condition::Duration(duration) = condition {
+        thread::sleep(*duration);
+        return Ok(());
+    }
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if holds(condition, background)? {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {timeout:?} waiting for {condition}"),
+            ));
+        }
+        thread::sleep(CHECK_INTERVAL.min(deadline - now));
+    }
+}
+
+fn holds(condition: &Condition, background: &mut Background) -> io::Result<bool> {
+    match condition {
+        Condition::File(path) => Ok(Path::new(&**path).exists()),
+        Condition::Port(port) => {
+            let address = SocketAddr::from((Ipv4Addr::LOCALHOST, *port));
+            Ok(TcpStream::connect_timeout(&address, CHECK_INTERVAL).is_ok())
+        }
+        Condition::Output(name, regex) => {
+            let (output, exited) = background.output(name)?;
+            if regex.is_match(&String::from_utf8_lossy(&output)) {
+                return Ok(true);
+            }
+            if exited {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("`{name}` exited before its output matched `{regex}`"),
+                ));
+            }
+            Ok(false)
+        }
+        Condition::Duration(_) => Ok(true),
+    }
+}
@@ -0,0 +1,307 @@
+use crate::directive::{self, Directive, DirectiveError};
+use crate::instruction::Instruction;
+use crate::parse::Parser;
+use crate::syntax::{Comment, Line};
+
+/// What happens to a line on the way from the old to the new contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Keep,
+    Delete,
+    // inserts the line of the new contents with this index
+    Insert(usize),
+}
+
+/// Instructions that turn the editor showing `old` into one showing `new`, going
+/// through the changed lines from top to bottom. Removed lines are erased one
+/// character at a time and added ones are typed, a changed line is both. A
+/// `//[WAIT]`, `//[PAUSE]`, `//[SPEED]` or `//[HIGHLIGHT]` in `new` applies
+/// before the first change below it, and `//[SKIP]` regions are left out. The other directives only
+/// make sense when the whole file is typed, so they are errors.
+///
+/// Returns an instruction that shows `old` at once, followed by the changes.
+pub fn edit_instructions(
+    old: Box<[Line<'_>]>,
+    new: &[Line<'_>],
+    comment: Comment,
+) -> Result<(Instruction, Vec<Instruction>), DirectiveError> {
+    let old = shown_lines(old, comment);
+    let old_text = old.iter().map(text).collect::<Vec<_>>();
+    let mut new_lines = vec![];
+    // for every directive, how many lines of `new` come before it
+    let mut directives = vec![];
+    // the line of an open `[SKIP]`
+    let mut skip = None;
+    for (index, line) in new.iter().enumerate() {
+        let error = |message: String| DirectiveError {
+            line: index + 1,
+            message,
+        };
+        let directive = match directive::parse(&line.text(), comment) {
+            Some(directive) => directive.map_err(error)?,
+            None if skip.is_some() => continue,
+            None => {
+                new_lines.push(line);
+                continue;
+            }
+        };
+        let inst = match directive {
+            Directive::EndSkip if skip.is_some() => {
+                skip = None;
+                continue;
+            }
+            _ if skip.is_some() => continue,
+            Directive::Skip => {
+                skip = Some(index + 1);
+                continue;
+            }
+            Directive::EndSkip => return Err(error("`[/SKIP]` without a `[SKIP]`".into())),
+            Directive::Wait => Instruction::Wait,
+            Directive::Pause(duration) => Instruction::Pause(duration.as_millis() as u64),
+            Directive::Speed(speed) => Instruction::SetSpeed(speed),
+            Directive::Highlight(lines) => Instruction::Highlight(lines),
+            _ => {
+                let message = format!(
+                    "`{}` can't be used with `set edit diff`",
+                    line.text().trim()
+                );
+                return Err(error(message));
+            }
+        };
+        directives.push((new_lines.len(), inst));
+    }
+    if let Some(line) = skip {
+        return Err(DirectiveError {
+            line,
+            message: "`[SKIP]` is never closed".into(),
+        });
+    }
+    let new_text = new_lines.iter().map(|line| text(line)).collect::<Vec<_>>();
+
+    let mut shown = Parser::new(old.into_boxed_slice(), comment)
+        .instructions()
+        .expect("the directives were left out");
+    // like a typed file, the cursor doesn't move past the last line
+    if let Some(Instruction::Newline { .. }) = shown.last() {
+        shown.pop();
+    }
+
+    let mut parser = Parser::new(Box::new([]), comment).human();
+    let mut instructions = vec![];
+    let mut directives = directives.into_iter().peekable();
+    // the row of the editor the next line of `old` is in
+    let mut row = 0;
+    // the next line of `old`
+    let mut old_next = 0;
+    // the next line of `new`
+    let mut next = 0;
+
+    for change in changes(&old_text, &new_text) {
+        if change != Change::Keep {
+            while let Some((_, inst)) = directives.next_if(|(at, _)| *at <= next) {
+                instructions.push(inst);
+            }
+        }
+        match change {
+            Change::Keep => {
+                row += 1;
+                next += 1;
+                old_next += 1;
+            }
+            Change::Delete => {
+                // erased from its end like with backspace, then the empty row is removed
+                let len = old_text[old_next].chars().count();
+                instructions.push(Instruction::MoveCursor(len as u16, row as u16));
+                instructions.extend(std::iter::repeat_n(Instruction::Backspace, len));
+                instructions.push(Instruction::DeleteLine);
+                old_next += 1;
+            }
+            Change::Insert(index) => {
+                instructions.push(Instruction::MoveCursor(0, row as u16));
+                instructions.push(Instruction::InsertLine);
+                instructions.extend(parser.line_instructions(new_lines[index]));
+                row += 1;
+                next += 1;
+            }
+        }
+    }
+    instructions.extend(directives.map(|(_, inst)| inst));
+
+    Ok((Instruction::Instant(shown), instructions))
+}
+
+/// The lines of `lines` that are shown, leaving out directives and the regions
+/// they skip. The old contents weren't necessarily written for scammed, so
+/// nothing in them is an error.
+fn shown_lines(lines: Box<[Line<'_>]>, comment: Comment) -> Vec<Line<'_>> {
+    let mut skipping = false;
+    let mut shown = vec![];
+    for line in lines.into_vec() {
+        match directive::parse(&line.text(), comment) {
+            Some(Ok(Directive::Skip)) => skipping = true,
+            Some(Ok(Directive::EndSkip)) => skipping = false,
+            Some(_) => (),
+            None if !skipping => shown.push(line),
+            None => (),
+        }
+    }
+    shown
+}
+
+fn text(line: &Line<'_>) -> String {
+    let mut text = line.text();
+    // the last line might only be missing its line break
+    text.truncate(text.trim_end_matches(['\r', '\n']).len());
+    text
+}
+
+/// The shortest way from `old` to `new`, found through their longest common
+/// subsequence of lines. Deletions come before the insertions replacing them.
+fn changes(old: &[String], new: &[String]) -> Vec<Change> {
+    // `common[i][j]` is the length of the longest common subsequence of
+    // `old[i..]` and `new[j..]`
+    let mut common = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = match old[i] == new[j] {
+                true => common[i + 1][j + 1] + 1,
+                false => common[i + 1][j].max(common[i][j + 1]),
+            };
+        }
+    }
+
+    let mut changes = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push(Change::Keep);
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+            changes.push(Change::Delete);
+            i += 1;
+        } else {
+            changes.push(Change::Insert(j));
+            j += 1;
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use syntect::highlighting::ThemeSet;
+
+    use super::{changes, edit_instructions, Change};
+    use crate::directive::DirectiveError;
+    use crate::instruction::Instruction;
+    use crate::render::render;
+    use crate::syntax;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    fn edit(old: &str, new: &str) -> Result<(Instruction, Vec<Instruction>), DirectiveError> {
+        let theme = &ThemeSet::load_defaults().themes["base16-ocean.dark"];
+        let old = syntax::highlight(old, "main.rs", theme);
+        let new = syntax::highlight(new, "main.rs", theme);
+        edit_instructions(old, &new, syntax::comment("main.rs"))
+    }
+
+    /// What the editor shows before and after the edits from `old` to `new`.
+    fn shown(old: &str, new: &str) -> (String, String) {
+        let (shown, edits) = edit(old, new).unwrap();
+        let before = render(std::slice::from_ref(&shown));
+        let mut instructions = vec![shown];
+        instructions.extend(edits);
+        (before, render(&instructions))
+    }
+
+    #[test]
+    fn changes_nothing_between_empty_contents() {
+        assert_eq!(changes(&[], &[]), []);
+    }
+
+    #[test]
+    fn keeps_identical_contents() {
+        let text = lines("a\nb\nc");
+        assert_eq!(changes(&text, &text), [Change::Keep; 3]);
+    }
+
+    #[test]
+    fn inserts_into_and_deletes_from_empty_contents() {
+        let text = lines("a\nb");
+        assert_eq!(changes(&[], &text), [Change::Insert(0), Change::Insert(1)]);
+        assert_eq!(changes(&text, &[]), [Change::Delete, Change::Delete]);
+    }
+
+    #[test]
+    fn deletes_a_changed_line_before_inserting_it() {
+        let changes = changes(&lines("a\nb\nc"), &lines("a\nB\nc\nd"));
+        let expected = [
+            Change::Keep,
+            Change::Delete,
+            Change::Insert(1),
+            Change::Keep,
+            Change::Insert(3),
+        ];
+        assert_eq!(changes, expected);
+    }
+
+    #[test]
+    fn edits_nothing_in_identical_contents() {
+        let src = "fn main() {\n    println!();\n}\n";
+        let (_, edits) = edit(src, src).unwrap();
+        assert!(edits.is_empty());
+        assert_eq!(shown(src, src).0, src.trim_end());
+    }
+
+    #[test]
+    fn edits_the_old_contents_into_the_new_ones() {
+        let old = "fn main() {\n    println!(\"a\");\n}\n";
+        let new = "use std::io;\n\nfn main() {\n    println!(\"b\");\n}\n";
+        let (before, after) = shown(old, new);
+        assert_eq!(before, old.trim_end());
+        assert_eq!(after, new.trim_end());
+    }
+
+    #[test]
+    fn edits_empty_contents() {
+        let src = "fn main() {}\n";
+        // the cursor ends up on the row after the typed line
+        assert_eq!(shown("", src), ("".into(), src.into()));
+        assert_eq!(shown(src, ""), (src.trim_end().into(), "".into()));
+    }
+
+    #[test]
+    fn erases_deleted_lines_with_backspace() {
+        let (_, edits) = edit("a\nbc\n", "a\n").unwrap();
+        assert!(matches!(
+            &*edits,
+            [
+                Instruction::MoveCursor(2, 1),
+                Instruction::Backspace,
+                Instruction::Backspace,
+                Instruction::DeleteLine,
+            ]
+        ));
+    }
+
+    #[test]
+    fn leaves_out_directives_and_skipped_lines() {
+        let new = "a\n//[WAIT]\nb\n//[SKIP]\nc\n//[/SKIP]\n";
+        let (_, edits) = edit("a\n", new).unwrap();
+        assert!(matches!(edits.first(), Some(Instruction::Wait)));
+        assert_eq!(shown("a\n", new).1, "a\nb\n");
+    }
+
+    #[test]
+    fn rejects_directives_that_type_the_whole_file() {
+        let error = edit("a\n", "a\n//[GOTO 1]\nb\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        let error = edit("a\n", "a\n//[SKIP]\nb\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "`[SKIP]` is never closed");
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::actions::parse_duration;
+use crate::syntax::Comment;
+
+/// A line like `//[WAIT]` in a file shown in the editor, which changes how the
+/// file is typed instead of being typed itself. Lines and columns are 0-based.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Directive {
+    // waits for a key press
+    Wait,
+    Pause(Duration),
+    // types {0} times as fast as usual
+    Speed(f64),
+    // shows everything up to `EndInstant` at once
+    Instant,
+    EndInstant,
+    // types the following lines above line {0}
+    Goto(u16),
+    // highlights the lines from {0}.0 through {0}.1, or nothing
+    Highlight(Option<(u16, u16)>),
+    // leaves out everything up to `EndSkip`
+    Skip,
+    EndSkip,
+    // removes {0} characters before the cursor
+    Backspace(usize),
+    // removes {0} characters after the cursor
+    Delete(usize),
+    DeleteLine,
+    // removes the text from {0} through {1}, as `(x, y)`
+    DeleteRange((u16, u16), (u16, u16)),
+    Join,
+}
+
+/// A directive that can't be used, with the 1-based line it is on.
+#[derive(Debug)]
+pub struct DirectiveError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for DirectiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Parses the raw source `line` if it is a directive: a `comment` holding nothing
+/// but `[NAME ...]`, where the name is written in capitals, like `//[WAIT]` or
+/// `<!-- [WAIT] -->`. Fails if it isn't one that exists.
+pub fn parse(line: &str, comment: Comment) -> Option<Result<Directive, String>> {
+    let inner = line
+        .trim()
+        .strip_prefix(comment.start)?
+        .strip_suffix(comment.end)?
+        .trim()
+        .strip_prefix('[')?
+        .strip_suffix(']')?;
+    // leaves comments like `// [1, 2, 3]` alone
+    if !inner.starts_with(|c: char| c.is_ascii_uppercase() || c == '/') {
+        return None;
+    }
+
+    let words = inner.split_whitespace().collect::<Vec<_>>();
+    let invalid = |expected: &str| Err(format!("`[{inner}]` expects {expected}"));
+    let directive = match &*words {
+        ["WAIT"] => Ok(Directive::Wait),
+        ["PAUSE", duration] => match parse_duration(duration) {
+            Some(duration) => Ok(Directive::Pause(duration)),
+            None => invalid("a duration like `800ms` or `2s`"),
+        },
+        ["SPEED", speed] => match speed
+            .strip_suffix('x')
+            .and_then(|speed| speed.parse::<f64>().ok())
+        {
+            Some(speed) if speed > 0.0 && speed.is_finite() => Ok(Directive::Speed(speed)),
+            _ => invalid("a speed like `2x` or `0.5x`"),
+        },
+        ["INSTANT"] => Ok(Directive::Instant),
+        ["/INSTANT"] => Ok(Directive::EndInstant),
+        ["GOTO", line] => match parse_line(line) {
+            Some(line) => Ok(Directive::Goto(line)),
+            None => invalid("a line number"),
+        },
+        ["HIGHLIGHT", "OFF"] => Ok(Directive::Highlight(None)),
+        ["HIGHLIGHT", lines] => {
+            let (first, last) = lines.split_once('-').unwrap_or((lines, lines));
+            match (parse_line(first), parse_line(last)) {
+                (Some(first), Some(last)) if first <= last => {
+                    Ok(Directive::Highlight(Some((first, last))))
+                }
+                _ => invalid("lines like `3-7`, a line or `OFF`"),
+            }
+        }
+        ["SKIP"] => Ok(Directive::Skip),
+        ["/SKIP"] => Ok(Directive::EndSkip),
+        ["BACKSPACE"] => Ok(Directive::Backspace(1)),
+        ["BACKSPACE", count] => match count.parse() {
+            Ok(count) => Ok(Directive::Backspace(count)),
+            Err(_) => invalid("a number of characters"),
+        },
+        ["DELETE", "LINE"] => Ok(Directive::DeleteLine),
+        ["DELETE", range] if range.contains('-') => {
+            let range = range
+                .split_once('-')
+                .and_then(|(from, to)| Some((position(from)?, position(to)?)));
+            match range {
+                Some((from, to)) if (from.1, from.0) <= (to.1, to.0) => {
+                    Ok(Directive::DeleteRange(from, to))
+                }
+                _ => invalid("a range like `2:5-3:1`"),
+            }
+        }
+        ["DELETE", count] => match count.parse() {
+            Ok(count) => Ok(Directive::Delete(count)),
+            Err(_) => invalid("a number of characters, `LINE` or a range like `2:5-3:1`"),
+        },
+        ["JOIN"] => Ok(Directive::Join),
+        _ => Err(format!("unknown directive `[{inner}]`")),
+    };
+    Some(directive)
+}
+
+/// `src` without the lines that are directives, with everything else as-is.
+pub fn strip(src: &str, comment: Comment) -> String {
+    let lines = src.split_inclusive('\n');
+    lines
+        .filter(|line| parse(line, comment).is_none())
+        .collect()
+}
+
+/// Parses a 1-based line number.
+fn parse_line(line: &str) -> Option<u16> {
+    line.parse::<u16>().ok()?.checked_sub(1)
+}
+
+/// Parses a 1-based `line:column` into a 0-based `(x, y)`.
+fn position(position: &str) -> Option<(u16, u16)> {
+    let (line, column) = position.split_once(':')?;
+    Some((parse_line(column)?, parse_line(line)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse, strip, Directive};
+    use crate::syntax::Comment;
+
+    const SLASHES: Comment = Comment {
+        start: "//",
+        end: "",
+    };
+    const MARKUP: Comment = Comment {
+        start: "<!--",
+        end: "-->",
+    };
+
+    fn directive(line: &str) -> Directive {
+        parse(line, SLASHES).unwrap().unwrap()
+    }
+
+    fn error(line: &str) -> String {
+        parse(line, SLASHES).unwrap().unwrap_err()
+    }
+
+    #[test]
+    fn parses_directives() {
+        assert_eq!(directive("//[WAIT]"), Directive::Wait);
+        assert_eq!(
+            directive("//[PAUSE 800ms]"),
+            Directive::Pause(Duration::from_millis(800))
+        );
+        assert_eq!(directive("//[SPEED 0.5x]"), Directive::Speed(0.5));
+        assert_eq!(directive("//[INSTANT]"), Directive::Instant);
+        assert_eq!(directive("//[/INSTANT]"), Directive::EndInstant);
+        assert_eq!(directive("//[GOTO 1]"), Directive::Goto(0));
+        assert_eq!(
+            directive("//[HIGHLIGHT 3-7]"),
+            Directive::Highlight(Some((2, 6)))
+        );
+        assert_eq!(
+            directive("//[HIGHLIGHT 4]"),
+            Directive::Highlight(Some((3, 3)))
+        );
+        assert_eq!(directive("//[HIGHLIGHT OFF]"), Directive::Highlight(None));
+        assert_eq!(directive("//[SKIP]"), Directive::Skip);
+        assert_eq!(directive("//[/SKIP]"), Directive::EndSkip);
+        assert_eq!(directive("//[JOIN]"), Directive::Join);
+    }
+
+    #[test]
+    fn parses_edits() {
+        assert_eq!(directive("//[BACKSPACE]"), Directive::Backspace(1));
+        assert_eq!(directive("//[BACKSPACE 8]"), Directive::Backspace(8));
+        assert_eq!(directive("//[DELETE 3]"), Directive::Delete(3));
+        assert_eq!(directive("//[DELETE LINE]"), Directive::DeleteLine);
+        assert_eq!(
+            directive("//[DELETE 2:5-3:1]"),
+            Directive::DeleteRange((4, 1), (0, 2))
+        );
+    }
+
+    #[test]
+    fn allows_whitespace_around_the_directive() {
+        assert_eq!(directive("    // [WAIT]\n"), Directive::Wait);
+        let line = "  <!-- [PAUSE 2s] -->\r\n";
+        let directive = parse(line, MARKUP).unwrap().unwrap();
+        assert_eq!(directive, Directive::Pause(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn leaves_other_lines_alone() {
+        for line in [
+            "let x = 1;",
+            "// [1, 2, 3]",
+            "// see [WAIT]",
+            "//[wait]",
+            "let x = [WAIT];",
+        ] {
+            assert!(parse(line, SLASHES).is_none(), "{line:?}");
+        }
+        assert!(parse("<!-- [WAIT]", MARKUP).is_none());
+        assert!(parse("//[WAIT]", MARKUP).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        assert_eq!(error("//[NAP]"), "unknown directive `[NAP]`");
+        assert_eq!(error("//[WAIT 2s]"), "unknown directive `[WAIT 2s]`");
+        assert!(error("//[PAUSE soon]").starts_with("`[PAUSE soon]` expects"));
+        assert!(error("//[SPEED 0x]").starts_with("`[SPEED 0x]` expects"));
+        assert!(error("//[SPEED 2]").starts_with("`[SPEED 2]` expects"));
+        assert!(error("//[GOTO 0]").starts_with("`[GOTO 0]` expects"));
+        assert!(error("//[HIGHLIGHT 7-3]").starts_with("`[HIGHLIGHT 7-3]` expects"));
+        assert!(error("//[DELETE 3:1-2:5]").starts_with("`[DELETE 3:1-2:5]` expects"));
+        assert!(error("//[BACKSPACE -1]").starts_with("`[BACKSPACE -1]` expects"));
+    }
+
+    #[test]
+    fn strips_directive_lines() {
+        let src = "fn main() {\n    //[WAIT]\n    // [1, 2]\n}\n//[NAP]";
+        assert_eq!(strip(src, SLASHES), "fn main() {\n    // [1, 2]\n}\n");
+    }
+}
@@ -0,0 +1,146 @@
+use std::env;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crossterm::style::{ContentStyle, Stylize};
+use syntect::highlighting::{Theme, ThemeSet};
+
+use crate::actions::{Action, ActionKind, Condition, EditMode, OnError};
+use crate::check::normalize;
+use crate::instruction::Instruction;
+use crate::pacing::Pacing;
+use crate::{diff, parse, resolve_path, syntax, THEME_PATH};
+
+/// Prints every action together with the directory it runs in and the files it
+/// touches, without running commands or copying files.
+pub fn print_plan(actions: &[Action]) {
+    let theme = ThemeSet::get_theme(THEME_PATH).ok();
+    let mut cwd = env::current_dir().expect("Failed to get current directory");
+    let width = actions.len().to_string().len();
+
+    for (index, action) in actions.iter().enumerate() {
+        println!(
+            "{:>width$} {}",
+            index + 1,
+            ContentStyle::default().cyan().apply(action)
+        );
+        for note in action.note.iter().flat_map(|note| note.lines()) {
+            println!(
+                "{:width$}   {}",
+                "",
+                ContentStyle::default().yellow().apply(note)
+            );
+        }
+        println!("{:width$}   in {}", "", cwd.display());
+        if let Some(cmd) = action.kind.command_line() {
+            if let Some(shell) = &cmd.shell {
+                println!("{:width$}   via {shell} -c", "");
+            }
+            if action.live {
+                println!("{:width$}   runs even when replaying outputs", "");
+            }
+            if action.on_error != OnError::Continue {
+                println!("{:width$}   on error: {}", "", action.on_error);
+            }
+            if cmd.pacing != Pacing::default() {
+                println!("{:width$}   shows its output {}", "", cmd.pacing);
+            }
+            if let Some(timeout) = cmd.timeout {
+                println!("{:width$}   killed after {timeout:?}", "");
+            }
+            for expectation in &cmd.expect {
+                println!(
+                    "{:width$}   answers {:?} with {:?} (waits up to {:?})",
+                    "", expectation.prompt, expectation.answer, expectation.timeout
+                );
+            }
+        }
+
+        match &action.kind {
+            ActionKind::ChangeDir(dir, _) | ActionKind::ChangeDirQuiet(dir) => {
+                cwd = normalize(&resolve_path(&cwd, dir));
+                println!("{:width$}   -> {}", "", cwd.display());
+            }
+            ActionKind::RunEditor(dst, src, mode, _) => {
+                let dst = normalize(&cwd.join(&**dst));
+                let src = normalize(&cwd.join(&**src));
+                println!("{:width$}   copy {}", "", src.display());
+                println!("{:width$}     to {}", "", dst.display());
+                if *mode == EditMode::Diff {
+                    println!("{:width$}   types only the lines that changed", "");
+                }
+                println!(
+                    "{:width$}   {}",
+                    "",
+                    describe_pauses(&src, &dst, *mode, theme.as_ref())
+                );
+            }
+            ActionKind::RunCommandWithInput(_, heredoc) => {
+                let lines = heredoc.body.lines().count();
+                println!("{:width$}   feeds {lines} line(s) to stdin", "");
+            }
+            ActionKind::RunBackground(name, _) => {
+                println!(
+                    "{:width$}   keeps running in the background as `{name}`",
+                    ""
+                );
+            }
+            ActionKind::WaitBackground(_, Some(timeout)) => {
+                println!("{:width$}   kills it after {timeout:?}", "");
+            }
+            ActionKind::WaitFor(Condition::Duration(_), _) => (),
+            ActionKind::FakeCommand(_, output, delay) => {
+                let lines = output.lines().count();
+                println!(
+                    "{:width$}   not run, prints {lines} line(s) of canned output {delay:?} apart",
+                    ""
+                );
+            }
+            ActionKind::WaitFor(condition, timeout) => {
+                println!("{:width$}   waits up to {timeout:?} for {condition}", "");
+            }
+            ActionKind::RunCommand(_)
+            | ActionKind::RunCommandQuiet(_)
+            | ActionKind::RunCommandOnlyOutput(_)
+            | ActionKind::KillBackground(_)
+            | ActionKind::WaitBackground(_, None) => (),
+        }
+    }
+}
+
+/// Counts the `Wait` pauses the editor would stop at for `src`, highlighted as `dst`.
+fn describe_pauses(src: &Path, dst: &Path, mode: EditMode, theme: Option<&Theme>) -> String {
+    let Some(theme) = theme else {
+        return format!("pauses unknown: failed to load theme {THEME_PATH}");
+    };
+    let Some(name) = dst.file_name().and_then(|name| name.to_str()) else {
+        return format!("pauses unknown: {} is not a file name", dst.display());
+    };
+    if !syntax::supports_file(name) {
+        return format!("pauses unknown: {name} can't be highlighted");
+    }
+    let code = match read_to_string(src) {
+        Ok(v) => v,
+        Err(e) => return format!("pauses unknown: failed to read source: {e}"),
+    };
+
+    let lines = syntax::highlight(&code, name, theme);
+    let comment = syntax::comment(name);
+    let instructions = match mode {
+        EditMode::Retype => parse::Parser::new(lines, comment).instructions(),
+        EditMode::Diff => {
+            diff::edit_instructions(Box::new([]), &lines, comment).map(|(_, insts)| insts)
+        }
+    };
+    let pauses = match instructions {
+        Ok(instructions) => instructions
+            .iter()
+            .filter(|inst| matches!(inst, Instruction::Wait))
+            .count(),
+        Err(e) => return format!("pauses unknown: {e}"),
+    };
+    match pauses {
+        1 => "1 pause".to_string(),
+        n => format!("{n} pauses"),
+    }
+}
@@ -0,0 +1,238 @@
+use std::fmt::{Display, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    // `+` got a different amount of arguments than a destination and a source file
+    EditorArgumentCount(usize),
+    // a `"` that is never closed
+    UnterminatedQuote,
+    // the line starts with a symbol that isn't an action prefix
+    UnknownPrefix(char),
+    // the line starts with a shell prompt like `$ `, as if copied from a terminal
+    PastedPrompt(char),
+    // `cd` without a target directory
+    EmptyCd,
+    // an `@` annotation that doesn't exist
+    UnknownAnnotation(Box<str>),
+    // `@note` without an action following it
+    DanglingNote,
+    // `${name}` with no value for `name`
+    UndefinedVariable(Box<str>),
+    // `${` without a closing `}`
+    UnterminatedVariable,
+    // a `let` that isn't `let name = value`
+    InvalidLet,
+    // an `include` that doesn't name exactly one scene
+    InvalidInclude,
+    // the included scene could not be read
+    IncludeFailed(Box<Path>, Box<str>),
+    // a scene that (indirectly) includes itself, with the chain of scenes
+    IncludeCycle(Box<str>),
+    // a heredoc without its closing delimiter
+    UnterminatedHeredoc(Box<str>),
+    // a heredoc on a `#` or `-` command
+    HiddenHeredoc,
+    // a `set` that isn't `set name value`
+    InvalidSet,
+    // `set` of a setting that doesn't exist
+    UnknownSetting(Box<str>),
+    // a value a setting doesn't accept, with the values it does accept
+    InvalidSettingValue(Box<str>, &'static str),
+    // an `expect` that isn't `expect "<prompt>" send "<answer>"`
+    InvalidExpect,
+    // an `expect` that doesn't follow a command whose output is shown
+    ExpectWithoutCommand,
+    // a duration that isn't a number followed by `ms`, `s` or `m`
+    InvalidDuration(Box<str>),
+    // an annotation before an action it doesn't apply to
+    MisplacedAnnotation(&'static str),
+    // `kill` or `wait` of a background process that is never started
+    UnknownBackground(Box<str>),
+    // a `wait` for a condition that doesn't exist or has the wrong arguments
+    InvalidWait,
+    // a port that isn't a number from 0 to 65535
+    InvalidPort(Box<str>),
+    // a regex that doesn't compile, with the reason
+    InvalidRegex(Box<str>),
+    // an `@output` that isn't a file or a heredoc
+    InvalidOutput,
+    // a file that could not be read
+    ReadFailed(PathBuf, Box<str>),
+    // a `redact` rule that doesn't exist or has the wrong arguments
+    InvalidRedact,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EditorArgumentCount(found) => f.write_fmt(format_args!(
+                "editor command `+` expects 2 arguments (a destination and a source file), found {found}"
+            )),
+            Self::UnterminatedQuote => f.write_str("unterminated quote"),
+            Self::UnknownPrefix(c) => f.write_fmt(format_args!(
+                "unknown prefix `{c}`, expected `#`, `-`, `+`, `&` or a command"
+            )),
+            Self::PastedPrompt(c) => f.write_fmt(format_args!(
+                "`{c}` looks like a prompt copied along with the command, remove it"
+            )),
+            Self::EmptyCd => f.write_str("`cd` expects a directory"),
+            Self::UnknownAnnotation(name) => f.write_fmt(format_args!(
+                "unknown annotation `@{name}`, expected `@note`, `@shell`, `@exec`, `@live`, `@on_error`, `@timeout`, `@name` or `@output`"
+            )),
+            Self::DanglingNote => f.write_str("`@note` is not followed by an action"),
+            Self::UndefinedVariable(name) => f.write_fmt(format_args!(
+                "variable `{name}` is not defined by a `let`, `--var` or the environment"
+            )),
+            Self::UnterminatedVariable => f.write_str("unterminated variable, expected `}`"),
+            Self::InvalidLet => f.write_str("expected `let <name> = <value>`"),
+            Self::InvalidInclude => f.write_str("expected `include \"<path>\"`"),
+            Self::IncludeFailed(path, e) => f.write_fmt(format_args!(
+                "failed to read included scene {}: {e}",
+                path.display()
+            )),
+            Self::IncludeCycle(chain) => f.write_fmt(format_args!("include cycle: {chain}")),
+            Self::UnterminatedHeredoc(delimiter) => f.write_fmt(format_args!(
+                "heredoc is never closed, expected a line with `{}`",
+                delimiter.trim_start_matches('-')
+            )),
+            Self::HiddenHeredoc => {
+                f.write_str("heredocs can only be used with commands that are shown")
+            }
+            Self::InvalidSet => f.write_str("expected `set <name> <value>`"),
+            Self::UnknownSetting(name) => f.write_fmt(format_args!(
+                "unknown setting `{name}`, expected `shell`, `on_error`, `status`, `output_delay`, `output_rate`, `output_lines` or `edit`"
+            )),
+            Self::InvalidSettingValue(value, expected) => f.write_fmt(format_args!(
+                "invalid value `{value}`, expected {expected}"
+            )),
+            Self::InvalidExpect => f.write_str(
+                "expected `expect \"<prompt>\" send \"<answer>\"`, optionally followed by `timeout <duration>`",
+            ),
+            Self::ExpectWithoutCommand => f.write_str(
+                "`expect` has to follow a command whose output is shown",
+            ),
+            Self::InvalidDuration(duration) => f.write_fmt(format_args!(
+                "invalid duration `{duration}`, expected something like `800ms`, `30s` or `2m`"
+            )),
+            Self::MisplacedAnnotation(name) => f.write_fmt(format_args!(
+                "`@{name}` can't be used with this action"
+            )),
+            Self::UnknownBackground(name) => f.write_fmt(format_args!(
+                "no background process named `{name}` is started before this, start one with `&`"
+            )),
+            Self::InvalidWait => f.write_str(
+                "expected `wait <duration>`, `wait file <path>`, `wait port <port>` or `wait output &<name> <regex>`",
+            ),
+            Self::InvalidPort(port) => f.write_fmt(format_args!("invalid port `{port}`")),
+            Self::InvalidRegex(e) => f.write_fmt(format_args!("invalid regex: {e}")),
+            Self::InvalidOutput => f.write_str("expected `@output \"<path>\"` or `@output <<<delimiter>`"),
+            Self::InvalidRedact => f.write_str(
+                "expected `redact home`, `redact keys`, `redact username <name>`, `redact hostname <name>` or `redact regex <regex> <replacement>`",
+            ),
+            Self::ReadFailed(path, e) => f.write_fmt(format_args!(
+                "failed to read {}: {e}",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// A single problem in a scene file, pointing at the offending part of a line.
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub width: usize,
+    pub source: Box<str>,
+    pub kind: ErrorKind,
+    // the scene file the problem is in
+    pub path: Box<Path>,
+    // the scenes and lines that (transitively) included `path`, innermost first
+    pub included_from: Vec<(PathBuf, usize)>,
+}
+
+impl Diagnostic {
+    /// `line` is 0-based, `span` is a byte range into `source`. The path is filled
+    /// in by the scene parser.
+    pub fn new(line: usize, source: &str, span: Range<usize>, kind: ErrorKind) -> Self {
+        // lines continued with `\` are joined, only show the one the span starts in
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        let line = line + source[..line_start].matches('\n').count();
+        let source = &source[line_start..line_end];
+        let span = span.start - line_start..span.end.min(line_end) - line_start;
+
+        let column = source[..span.start].chars().count() + 1;
+        let width = source[span].chars().count().max(1);
+        Self {
+            line: line + 1,
+            column,
+            width,
+            source: source.into(),
+            kind,
+            path: Path::new("").into(),
+            included_from: vec![],
+        }
+    }
+}
+
+/// Every problem found while parsing a scene file and the scenes it includes.
+#[derive(Debug)]
+pub struct SceneError {
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = self
+            .diagnostics
+            .iter()
+            .map(|d| d.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        for diagnostic in &self.diagnostics {
+            f.write_fmt(format_args!("error: {}\n", diagnostic.kind))?;
+            f.write_fmt(format_args!(
+                "{:gutter$}--> {}:{}:{}\n",
+                "",
+                diagnostic.path.display(),
+                diagnostic.line,
+                diagnostic.column
+            ))?;
+            f.write_fmt(format_args!("{:gutter$} |\n", ""))?;
+            f.write_fmt(format_args!(
+                "{:>gutter$} | {}\n",
+                diagnostic.line, diagnostic.source
+            ))?;
+            f.write_fmt(format_args!("{:gutter$} | ", ""))?;
+            // keep tabs so the carets line up with the snippet above
+            for c in diagnostic.source.chars().take(diagnostic.column - 1) {
+                f.write_char(if c == '\t' { '\t' } else { ' ' })?;
+            }
+            f.write_str(&"^".repeat(diagnostic.width))?;
+            f.write_char('\n')?;
+            for (path, line) in &diagnostic.included_from {
+                f.write_fmt(format_args!(
+                    "{:gutter$} = note: included from {}:{line}\n",
+                    "",
+                    path.display()
+                ))?;
+            }
+            f.write_char('\n')?;
+        }
+
+        match self.diagnostics.len() {
+            1 => f.write_str("could not parse scene due to 1 previous error"),
+            n => f.write_fmt(format_args!(
+                "could not parse scene due to {n} previous errors"
+            )),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
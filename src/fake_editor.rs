@@ -1,3 +1,4 @@
+use std::ops::Range;
 use std::{env, path::PathBuf, sync::mpsc::Sender};
 
 use anathema::component::*;
@@ -15,12 +16,14 @@ use crate::{
 #[derive(State)]
 struct Line {
     spans: Value<List<Span>>,
+    highlighted: Value<bool>,
 }
 
 impl Line {
     pub fn empty() -> Self {
         Self {
             spans: List::empty(),
+            highlighted: false.into(),
         }
     }
 }
@@ -129,6 +132,57 @@ impl Doc {
         *self.tree.to_mut() = tree;
         self.folder_list = folder_list;
     }
+
+    fn line_len(&mut self, y: usize) -> usize {
+        let mut lines = self.lines.to_mut();
+        lines.get_mut(y).map_or(0, |line| line.to_mut().spans.len())
+    }
+
+    /// The characters of line `y` with their foreground and boldness.
+    fn line_chars(&mut self, y: usize) -> Vec<(char, Hex, bool)> {
+        let mut chars = vec![];
+        let mut lines = self.lines.to_mut();
+        if let Some(line) = lines.get_mut(y) {
+            line.to_mut().spans.for_each(|span| {
+                chars.push((
+                    span.text.copy_value(),
+                    span.foreground.copy_value(),
+                    span.bold.copy_value(),
+                ));
+            });
+        }
+        chars
+    }
+
+    /// Removes the characters in `range` from line `y`, as far as it has them.
+    fn remove_chars(&mut self, y: usize, range: Range<usize>) {
+        let mut lines = self.lines.to_mut();
+        let Some(line) = lines.get_mut(y) else { return };
+        let mut line = line.to_mut();
+        let end = range.end.min(line.spans.len());
+        for _ in range.start.min(end)..end {
+            line.spans.remove(range.start);
+        }
+    }
+
+    fn push_chars(&mut self, y: usize, chars: Vec<(char, Hex, bool)>) {
+        let mut lines = self.lines.to_mut();
+        let Some(line) = lines.get_mut(y) else { return };
+        let mut line = line.to_mut();
+        for (c, foreground, bold) in chars {
+            line.spans.push_back(Span::new(c, foreground, bold));
+        }
+    }
+
+    /// Appends line `y + 1` to line `y`.
+    fn join_lines(&mut self, y: usize) {
+        if y + 1 >= self.lines.len() {
+            return;
+        }
+        let chars = self.line_chars(y + 1);
+        self.lines.remove(y + 1);
+        self.push_chars(y, chars);
+    }
 }
 
 pub struct Editor {
@@ -190,52 +244,135 @@ impl Editor {
         elements.query().by_tag("overflow").first(|el, _| {
             let size = el.size();
             let vp = el.to::<Overflow>();
+            self.apply(&inst, doc, vp, size);
+        });
+    }
 
-            match &inst {
-                Instruction::MoveCursor(x, y) => {
-                    self.cursor.x = *x as i32;
-                    self.cursor.y = *y as i32;
-                    self.update_cursor(doc, vp, size);
+    fn apply(&mut self, inst: &Instruction, doc: &mut Doc, vp: &mut Overflow, size: Size) {
+        match inst {
+            Instruction::MoveCursor(x, y) => {
+                self.cursor.x = *x as i32;
+                self.cursor.y = *y as i32;
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::Type(c, bold) => {
+                {
+                    let mut lines = doc.lines.to_mut();
+                    let line = lines.get_mut(self.cursor.y as usize).unwrap();
+                    let mut line = line.to_mut();
+                    line.spans.insert(
+                        self.cursor.x as usize,
+                        Span::new(*c, self.foreground, *bold),
+                    );
+                    self.cursor.x += 1;
                 }
-                Instruction::Type(c, bold) => {
-                    {
-                        let mut lines = doc.lines.to_mut();
-                        let line = lines.get_mut(self.cursor.y as usize).unwrap();
-                        let mut line = line.to_mut();
-                        line.spans.insert(
-                            self.cursor.x as usize,
-                            Span::new(*c, self.foreground, *bold),
-                        );
-                        self.cursor.x += 1;
-                    }
 
-                    self.update_cursor(doc, vp, size);
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::InsertLine => {
+                let y = (self.cursor.y as usize).min(doc.lines.len());
+                doc.lines.insert(y, Line::empty());
+                self.cursor.x = 0;
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::DeleteLine => {
+                let y = self.cursor.y as usize;
+                if y < doc.lines.len() {
+                    doc.lines.remove(y);
                 }
-                Instruction::SetForeground(hex) => self.foreground = *hex,
-                Instruction::Newline { x } => {
-                    self.cursor.x = *x;
-                    self.cursor.y += 1;
-                    self.update_cursor(doc, vp, size);
+                self.cursor.x = 0;
+                // adds an empty line again if that was the last one
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::Backspace => {
+                let y = self.cursor.y as usize;
+                if self.cursor.x > 0 {
+                    self.cursor.x -= 1;
+                    doc.remove_chars(y, self.cursor.x as usize..self.cursor.x as usize + 1);
+                } else if y > 0 {
+                    // at the start of a line, it is joined with the line above
+                    self.cursor.x = doc.line_len(y - 1) as i32;
+                    self.cursor.y -= 1;
+                    doc.join_lines(y - 1);
                 }
-                Instruction::SetX(x) => {
-                    self.cursor.x = *x as i32;
-                    self.update_cursor(doc, vp, size);
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::DeleteForward => {
+                let (x, y) = (self.cursor.x as usize, self.cursor.y as usize);
+                match x < doc.line_len(y) {
+                    true => doc.remove_chars(y, x..x + 1),
+                    // at the end of a line, the line below is joined with it
+                    false => doc.join_lines(y),
                 }
-                Instruction::Pause(_) => unreachable!(),
-                Instruction::Wait => doc.waiting.set(true.to_string()),
-                Instruction::HideCursor => {
-                    doc.show_cursor.set(false);
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::DeleteRange { from, to } => {
+                let (start, end) = (
+                    (from.0 as usize, from.1 as usize),
+                    (to.0 as usize, to.1 as usize),
+                );
+                if start.1 == end.1 {
+                    doc.remove_chars(start.1, start.0..end.0);
+                } else if start.1 < end.1 {
+                    // keeps the start of the first line and the end of the last one
+                    doc.remove_chars(start.1, start.0..usize::MAX);
+                    doc.remove_chars(end.1, 0..end.0);
+                    for _ in start.1 + 1..end.1.min(doc.lines.len()) {
+                        doc.lines.remove(start.1 + 1);
+                    }
+                    doc.join_lines(start.1);
                 }
-                Instruction::WaitForQuit => {}
-                Instruction::UpdateState(new_focused, new_transmitter) => {
-                    self.ack = new_transmitter.clone();
-                    doc.update_state(
-                        new_focused.display().to_string().into(),
-                        new_focused.clone(),
-                    );
+                self.cursor.x = from.0 as i32;
+                self.cursor.y = from.1 as i32;
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::JoinLines => {
+                let y = self.cursor.y as usize;
+                self.cursor.x = doc.line_len(y) as i32;
+                doc.join_lines(y);
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::SetForeground(hex) => self.foreground = *hex,
+            Instruction::Newline { x } => {
+                self.cursor.x = *x;
+                self.cursor.y += 1;
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::SetX(x) => {
+                self.cursor.x = *x as i32;
+                self.update_cursor(doc, vp, size);
+            }
+            Instruction::Highlight(range) => {
+                let len = doc.lines.len();
+                let mut lines = doc.lines.to_mut();
+                for y in 0..len {
+                    let Some(line) = lines.get_mut(y) else {
+                        continue;
+                    };
+                    let highlighted = range
+                        .is_some_and(|(first, last)| (first as usize..=last as usize).contains(&y));
+                    line.to_mut().highlighted.set(highlighted);
                 }
             }
-        });
+            Instruction::Pause(_) | Instruction::SetSpeed(_) => unreachable!(),
+            Instruction::Wait => doc.waiting.set(true.to_string()),
+            Instruction::HideCursor => {
+                doc.show_cursor.set(false);
+            }
+            Instruction::WaitForQuit | Instruction::FileOnly(_) => {}
+            Instruction::UpdateState(new_focused, new_transmitter) => {
+                self.ack = new_transmitter.clone();
+                doc.update_state(
+                    new_focused.display().to_string().into(),
+                    new_focused.clone(),
+                );
+            }
+            Instruction::Instant(instructions) => {
+                for inst in instructions {
+                    self.apply(inst, doc, vp, size);
+                }
+            }
+        }
     }
 }
 
@@ -0,0 +1,95 @@
+use std::sync::{Mutex, OnceLock};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::TYPO_PAUSE_RANGE_MS;
+
+/// Keyboard rows, each shifted by half a key to the right of the one above.
+const QWERTY_ROWS: [&str; 4] = ["1234567890-=", "qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./"];
+
+/// How likely a typed character is to be a typo, as set by `--typos`.
+static TYPO_CHANCE: OnceLock<f64> = OnceLock::new();
+/// Every random choice made while typing, so a `--seed` makes a take reproducible.
+static RNG: Mutex<Option<StdRng>> = Mutex::new(None);
+
+/// A mistake made while typing a character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Typo {
+    // a key next to the right one was hit instead
+    Neighbour(char),
+    // the character was typed after the next one, which is {0}
+    Transpose(char),
+}
+
+/// Makes a typo in `chance` of the typed characters from now on.
+pub fn install(chance: f64) {
+    _ = TYPO_CHANCE.set(chance);
+}
+
+/// Makes every random choice from now on depend on `seed` only.
+pub fn seed(seed: u64) {
+    *RNG.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    let mut rng = RNG.lock().unwrap();
+    f(rng.get_or_insert_with(StdRng::from_entropy))
+}
+
+/// Decides whether typing `c`, followed by `next`, goes wrong and how.
+pub fn typo(c: char, next: Option<char>) -> Option<Typo> {
+    let chance = *TYPO_CHANCE.get()?;
+    with_rng(|rng| {
+        if !rng.gen_bool(chance) {
+            return None;
+        }
+        let transposable = next.filter(|&next| next != c && next.is_alphanumeric());
+        if let Some(next) = transposable.filter(|_| c.is_alphanumeric() && rng.gen_bool(0.3)) {
+            return Some(Typo::Transpose(next));
+        }
+        let neighbours = neighbours(c);
+        if neighbours.is_empty() {
+            return None;
+        }
+        Some(Typo::Neighbour(
+            neighbours[rng.gen_range(0..neighbours.len())],
+        ))
+    })
+}
+
+/// How long it takes to notice a typo, in milliseconds.
+pub fn notice_delay() -> u64 {
+    with_rng(|rng| rng.gen_range(TYPO_PAUSE_RANGE_MS))
+}
+
+/// The keys around `c` on a QWERTY keyboard, in the same case.
+fn neighbours(c: char) -> Vec<char> {
+    let lower = c.to_ascii_lowercase();
+    let Some((row, column)) = QWERTY_ROWS
+        .iter()
+        .enumerate()
+        .find_map(|(row, keys)| Some((row, keys.find(lower)?)))
+    else {
+        return vec![];
+    };
+
+    let key = |row: usize, column: Option<usize>| QWERTY_ROWS.get(row)?.chars().nth(column?);
+    let neighbours = [
+        key(row, column.checked_sub(1)),
+        key(row, Some(column + 1)),
+        // the row above is shifted to the left, the one below to the right
+        key(row.wrapping_sub(1), Some(column)),
+        key(row.wrapping_sub(1), Some(column + 1)),
+        key(row + 1, column.checked_sub(1)),
+        key(row + 1, Some(column)),
+    ];
+    neighbours
+        .into_iter()
+        .flatten()
+        .map(|key| match c.is_ascii_uppercase() {
+            true => key.to_ascii_uppercase(),
+            false => key,
+        })
+        .collect()
+}
@@ -6,12 +6,33 @@ use anathema::state::Hex;
 pub enum Instruction {
     MoveCursor(u16, u16),
     Type(char, bool),
+    // inserts an empty line at the cursor, moving the lines from there down
+    InsertLine,
+    // removes the line at the cursor, moving the lines below it up
+    DeleteLine,
+    // removes the character before the cursor, joining the line with the one
+    // above at its start
+    Backspace,
+    // removes the character at the cursor, joining the line below at the end
+    DeleteForward,
+    // removes the text from `from` up to `to`, as `(x, y)`, and moves the cursor to `from`
+    DeleteRange { from: (u16, u16), to: (u16, u16) },
+    // appends the line below the cursor to the cursor's line
+    JoinLines,
     SetForeground(Hex),
     Newline { x: i32 },
     SetX(i32),
     Pause(u64),
+    // types this many times as fast as usual from now on
+    SetSpeed(f64),
+    // highlights the lines from .0 through .1, or no lines
+    Highlight(Option<(u16, u16)>),
+    // a line that is written to the file above the cursor's row, but never shown
+    FileOnly(Box<str>),
     Wait,
     WaitForQuit,
     UpdateState(PathBuf, Sender<()>),
     HideCursor,
+    // applies the instructions right away, without typing delays
+    Instant(Vec<Instruction>),
 }
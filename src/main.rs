@@ -1,40 +1,63 @@
 use core::str;
+use std::collections::HashMap;
 use std::env;
 use std::env::home_dir;
 use std::fmt::Display;
 use std::fs::read_to_string;
-use std::io::{Read, Write};
+use std::io::{self, Write};
 use std::ops::Range;
-use std::path::PathBuf;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
 use std::sync::mpsc::{self, Receiver};
 use std::thread;
 use std::time::Duration;
 
-use actions::{parse_actions, Action};
+use actions::{parse_scene, Action, ActionKind, EditMode, Heredoc, OnError, Scene, ShowStatus};
 use anathema::backend::tui::Screen;
 use anathema::component::{ComponentId, Emitter};
 use anathema::prelude::*;
-use command::{run_command, run_command_quiet};
+use background::Background;
+use command::{run_command, run_command_quiet, run_command_with_input};
 use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Colored, ContentStyle, Stylize};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, Clear, ClearType, EnterAlternateScreen,
 };
 use crossterm::{cursor, ExecutableCommand};
+use directive::DirectiveError;
 use fake_editor::{Doc, Editor};
+use human::Typo;
 use quittable_backend::{QuittableTuiBackend, SHOULD_QUIT};
 use rand::Rng;
-use syntect::highlighting::ThemeSet;
+use recording::Outputs;
+use sandbox::Sandbox;
+use syntax::Comment;
+use syntect::highlighting::{Theme, ThemeSet};
 
 use self::instruction::Instruction;
 
 mod actions;
+mod background;
+mod check;
 mod command;
+mod condition;
+mod diff;
+mod directive;
+mod dry_run;
+mod error;
 mod fake_editor;
 mod file_tree;
+mod human;
 mod instruction;
+mod pacing;
 mod parse;
+mod pty;
 mod quittable_backend;
+mod recording;
+mod redact;
+mod render;
+mod sandbox;
 pub(crate) mod syntax;
 
 /// -----------------------
@@ -42,20 +65,98 @@ pub(crate) mod syntax;
 /// -----------------------
 
 const TYPING_DELAY_RANGE_MS: Range<u64> = 35..85;
+// how long it takes to notice a typo made with `--typos` before correcting it
+const TYPO_PAUSE_RANGE_MS: Range<u64> = 250..600;
+const THEME_PATH: &str = "themes/custom.stTheme";
+// runs commands with a heredoc, which is shell syntax, unless the scene has a `set shell`
+const DEFAULT_SHELL: &str = "sh";
+// how long `expect` waits for its prompt unless it has a `timeout`
+const EXPECT_TIMEOUT: Duration = Duration::from_secs(30);
+// how long `wait` waits for its condition unless it has a `@timeout`
+const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+// pause between the lines of `@output`, unless the scene has a `set output_delay`
+const FAKE_OUTPUT_DELAY: Duration = Duration::from_millis(40);
 
 /// -----------------------
 
+fn typing_delay() -> Duration {
+    Duration::from_millis(human::with_rng(|rng| rng.gen_range(TYPING_DELAY_RANGE_MS)))
+}
+
 fn sleep_between_characters() {
-    let sleep = rand::thread_rng().gen_range(TYPING_DELAY_RANGE_MS);
-    thread::sleep(Duration::from_millis(sleep));
+    thread::sleep(typing_delay());
 }
 
-fn insts(lines: Box<[syntax::Line<'_>]>) -> Vec<Instruction> {
-    let mut instructions = parse::Parser::new(lines).instructions();
-    instructions.pop();
+fn insts(
+    lines: Box<[syntax::Line<'_>]>,
+    comment: Comment,
+) -> Result<Vec<Instruction>, DirectiveError> {
+    let mut instructions = parse::Parser::new(lines, comment).human().instructions()?;
+    if let Some(Instruction::Newline { .. }) = instructions.last() {
+        instructions.pop();
+    }
     instructions.insert(0, Instruction::Pause(1000));
     instructions.push(Instruction::WaitForQuit);
-    instructions
+    Ok(instructions)
+}
+
+/// Shows `old` right away and then types the changes that turn it into `new`.
+fn diff_insts(
+    old: Box<[syntax::Line<'_>]>,
+    new: &[syntax::Line<'_>],
+    comment: Comment,
+) -> Result<Vec<Instruction>, DirectiveError> {
+    let (shown, mut instructions) = diff::edit_instructions(old, new, comment)?;
+    instructions.insert(0, Instruction::Pause(1000));
+    instructions.insert(0, shown);
+    instructions.push(Instruction::WaitForQuit);
+    Ok(instructions)
+}
+
+/// The instructions that show `src` being typed into `dst`, which is highlighted
+/// by its name, and the text `dst` has in the end. Fails on anything that would
+/// stop the editor, like a directive in `src` that can't be used.
+fn editor_instructions(
+    src: &Path,
+    dst: &Path,
+    mode: EditMode,
+    theme: &Theme,
+) -> io::Result<(Vec<Instruction>, String)> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let Some(name) = dst.file_name().and_then(|name| name.to_str()) else {
+        return Err(invalid(format!("{} is not a file name", dst.display())));
+    };
+    if !syntax::supports_file(name) {
+        return Err(invalid(format!(
+            "no syntax highlighting available for {name}"
+        )));
+    }
+    let code = read_to_string(src)
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to read {}: {e}", src.display())))?;
+    // a file that doesn't exist yet is typed out completely
+    let old = match mode {
+        EditMode::Retype => String::new(),
+        EditMode::Diff => read_to_string(dst).unwrap_or_default(),
+    };
+
+    let lines = syntax::highlight(&code, name, theme);
+    let comment = syntax::comment(name);
+    let instructions = match mode {
+        EditMode::Retype => insts(lines, comment),
+        EditMode::Diff => diff_insts(syntax::highlight(&old, name, theme), &lines, comment),
+    };
+    let instructions = instructions.map_err(|e| invalid(format!("{}: {e}", src.display())))?;
+
+    // what the editor shows in the end, with the edits and without the directives
+    let mut text = match mode {
+        EditMode::Retype => render::render(&instructions),
+        // the changes end up showing `src`, so only the directives have to go
+        EditMode::Diff => directive::strip(&code, comment),
+    };
+    if code.ends_with('\n') && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    Ok((instructions, text))
 }
 
 fn enable_tui() {
@@ -71,80 +172,269 @@ fn disable_tui() {
     let _ = screen.restore(std::io::stdout());
 }
 
-fn main() {
+fn load_scene(path: &str, variables: &HashMap<Box<str>, Box<str>>) -> Scene {
+    let action_file = match read_to_string(path) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Failed to read {path}: {e}");
+            std::process::exit(1);
+        }
+    };
+    match parse_scene(path.as_ref(), action_file, variables) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+const USAGE: &str = "Usage: scammed [check] [--dry-run] [--var <key>=<value>]... [--record-outputs <dir> | --replay-outputs <dir>] [--sandbox [--fixture <dir>] [--keep]] [--typos <chance>] [--seed <number>] <scene>";
+
+struct Options {
+    // only validate the scene
+    check: bool,
+    // only print what the scene would do
+    dry_run: bool,
+    // `--var` values, overriding variables of the scene
+    variables: HashMap<Box<str>, Box<str>>,
+    // whether command output is recorded or played back
+    outputs: Outputs,
+    // run the scene in a temporary directory
+    sandbox: bool,
+    // directory whose contents are copied into the sandbox
+    fixture: Option<PathBuf>,
+    // don't remove the sandbox afterwards
+    keep: bool,
+    // how likely a typed character is to be a typo, from 0 to 1
+    typos: Option<f64>,
+    // seed for every random choice while typing
+    seed: Option<u64>,
+    path: String,
+}
+
+fn parse_args() -> Options {
+    let mut check = false;
+    let mut dry_run = false;
+    let mut variables = HashMap::new();
+    let mut outputs = Outputs::Live;
+    let mut sandbox = false;
+    let mut fixture = None;
+    let mut keep = false;
+    let mut typos = None;
+    let mut seed = None;
+    let mut path = None;
+    let cwd = env::current_dir().expect("Failed to get current directory");
+
     let mut args = std::env::args().skip(1);
-    let path = args.next().unwrap();
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "check" if !check && path.is_none() => check = true,
+            "--dry-run" => dry_run = true,
+            "--var" => {
+                let var = args.next().unwrap_or_default();
+                let Some((key, value)) = var.split_once('=') else {
+                    eprintln!("--var expects <key>=<value>\n{USAGE}");
+                    std::process::exit(2);
+                };
+                variables.insert(key.into(), value.into());
+            }
+            "--record-outputs" | "--replay-outputs" => {
+                if !matches!(outputs, Outputs::Live) {
+                    eprintln!("Outputs can't be recorded and replayed at the same time\n{USAGE}");
+                    std::process::exit(2);
+                }
+                let Some(dir) = args.next() else {
+                    eprintln!("{arg} expects a directory\n{USAGE}");
+                    std::process::exit(2);
+                };
+                // the scene might run somewhere else
+                let dir = resolve_path(&cwd, &dir);
+                outputs = match &*arg {
+                    "--record-outputs" => Outputs::Record(dir),
+                    _ => Outputs::Replay(dir),
+                };
+            }
+            "--sandbox" => sandbox = true,
+            "--fixture" => {
+                let Some(dir) = args.next() else {
+                    eprintln!("--fixture expects a directory\n{USAGE}");
+                    std::process::exit(2);
+                };
+                fixture = Some(resolve_path(&cwd, &dir));
+            }
+            "--keep" => keep = true,
+            "--typos" => {
+                let chance = args.next().and_then(|chance| chance.parse().ok());
+                let Some(chance) = chance.filter(|chance| (0.0..=1.0).contains(chance)) else {
+                    eprintln!("--typos expects a chance from 0 to 1, like 0.02\n{USAGE}");
+                    std::process::exit(2);
+                };
+                typos = Some(chance);
+            }
+            "--seed" => {
+                let Some(number) = args.next().and_then(|number| number.parse().ok()) else {
+                    eprintln!("--seed expects a number\n{USAGE}");
+                    std::process::exit(2);
+                };
+                seed = Some(number);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option {arg}\n{USAGE}");
+                std::process::exit(2);
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("Unexpected argument {arg}\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
 
-    let action_file = read_to_string(&path).unwrap();
-    let actions = parse_actions(action_file);
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        std::process::exit(2);
+    };
+    if !sandbox && (fixture.is_some() || keep) {
+        eprintln!("--fixture and --keep only work with --sandbox\n{USAGE}");
+        std::process::exit(2);
+    }
+    Options {
+        check,
+        dry_run,
+        variables,
+        outputs,
+        sandbox,
+        fixture,
+        keep,
+        typos,
+        seed,
+        path,
+    }
+}
+
+fn main() {
+    let options = parse_args();
+    let path = options.path;
+    let outputs = options.outputs;
+    let Scene {
+        actions,
+        redactions,
+    } = load_scene(&path, &options.variables);
+
+    if options.check {
+        std::process::exit(check::check_scene(&path, &actions));
+    }
+    if options.dry_run {
+        dry_run::print_plan(&actions);
+        return;
+    }
+    redact::install(redactions);
+    if let Some(chance) = options.typos {
+        human::install(chance);
+    }
+    if let Some(seed) = options.seed {
+        human::seed(seed);
+    }
 
     let mut output = std::io::stdout();
     _ = output.execute(MoveTo(0, 0));
     _ = output.execute(Clear(ClearType::All));
 
-    let theme = ThemeSet::get_theme("themes/custom.stTheme").unwrap();
+    let theme = ThemeSet::get_theme(THEME_PATH).unwrap();
     let base_path = env::current_dir().expect("Failed to get current directory");
+    let sandbox = options.sandbox.then(|| {
+        let sandbox = Sandbox::create(options.fixture.as_deref(), options.keep)
+            .unwrap_or_else(|e| panic!("Failed to create sandbox: {e}"));
+        env::set_current_dir(sandbox.path()).expect("Failed to enter sandbox");
+        sandbox
+    });
+
+    let mut background = Background::new();
+    let mut failures: Vec<(usize, Box<str>)> = vec![];
+    // exit code of the last command that was shown
+    let mut last_code = 0;
+    let mut index = 0;
 
     wait_for_input();
-    for action in actions.iter() {
-        match action {
-            Action::ChangeDir(dir) => {
-                print_fake_cmd();
-                write_command(["cd", &**dir].iter().map(|el| *el));
-                cd(&**dir);
-                wait_for_input();
+    while let Some(action) = actions.get(index) {
+        // `@live` commands run for what they do even when replaying
+        let outputs = match (&outputs, action.live) {
+            (Outputs::Replay(_), true) => &Outputs::Live,
+            (outputs, _) => outputs,
+        };
+        let result = match &action.kind {
+            ActionKind::ChangeDir(dir, text) => {
+                print_fake_cmd(action.show_status, last_code);
+                write_command(text);
+                cd(dir).err().map(Err)
             }
-            Action::ChangeDirQuiet(dir) => {
-                cd(&**dir);
+            ActionKind::ChangeDirQuiet(dir) => cd(dir).err().map(Err),
+            ActionKind::RunCommand(cmd) => {
+                print_fake_cmd(action.show_status, last_code);
+                write_command(&cmd.text);
+                Some(
+                    outputs.run(index, &action.to_string(), Some(cmd.pacing), || {
+                        run_command(cmd)
+                    }),
+                )
             }
-            Action::RunCommand(cmd) => {
-                print_fake_cmd();
-                write_command(cmd.iter().map(|el| &**el));
-                match run_command(cmd) {
-                    Ok(_) => (),
-                    Err(e) => eprintln!("{}", ContentStyle::default().red().apply(e)),
-                }
-                wait_for_input();
+            ActionKind::RunCommandWithInput(cmd, heredoc) => {
+                print_fake_cmd(action.show_status, last_code);
+                write_command(&cmd.text);
+                write_heredoc(heredoc);
+                Some(
+                    outputs.run(index, &action.to_string(), Some(cmd.pacing), || {
+                        run_command_with_input(cmd, heredoc)
+                    }),
+                )
             }
-            Action::RunCommandOnlyOutput(cmd) => match run_command(cmd) {
-                Ok(_) => (),
-                Err(e) => eprintln!("{}", ContentStyle::default().red().apply(e)),
-            },
-            Action::RunCommandQuiet(cmd) => match run_command_quiet(cmd) {
-                Ok(_) => (),
-                Err(e) => eprintln!("{}", ContentStyle::default().red().apply(e)),
-            },
-            Action::RunEditor(dst, src) => {
-                print_fake_cmd();
-                write_command(["edit", dst].iter().map(|el| *el));
-                let Ok(dir) = env::current_dir() else {
-                    panic!("Could not acquire current directory")
+            ActionKind::FakeCommand(cmd, output, delay) => {
+                print_fake_cmd(action.show_status, last_code);
+                write_command(&cmd.text);
+                print_fake_output(output, *delay);
+                last_code = 0;
+                None
+            }
+            ActionKind::RunCommandOnlyOutput(cmd) => Some(outputs.run(
+                index,
+                &action.to_string(),
+                Some(cmd.pacing),
+                || run_command(cmd),
+            )),
+            ActionKind::RunCommandQuiet(cmd) => {
+                Some(outputs.run(index, &action.to_string(), None, || run_command_quiet(cmd)))
+            }
+            ActionKind::RunBackground(name, cmd) => background.start(name, cmd).err().map(Err),
+            ActionKind::KillBackground(name) => background.kill(name).err().map(Err),
+            ActionKind::WaitBackground(name, timeout) => Some(background.wait(name, *timeout)),
+            ActionKind::WaitFor(condition, timeout) => {
+                condition::wait_for(condition, *timeout, &mut background)
+                    .err()
+                    .map(Err)
+            }
+            ActionKind::RunEditor(dst, src, mode, dst_text) => 'edit: {
+                let dir = match env::current_dir() {
+                    Ok(v) => v,
+                    Err(e) => break 'edit Some(Err(e)),
                 };
                 let dst = dir.join(&**dst);
                 let src = dir.join(&**src);
-                match std::fs::copy(&src, &dst) {
-                    Ok(_) => (),
-                    Err(e) => panic!(
-                        "Failed to copy from {} to {}: {e}",
-                        src.display(),
-                        dst.display()
-                    ),
-                }
-
-                let Some(extension) = dst.extension() else {
-                    panic!("File {} does not have an extension", dst.display())
-                };
-                let extension = match str::from_utf8(extension.as_encoded_bytes()) {
+                // fails before anything is shown
+                let (instructions, text) = match editor_instructions(&src, &dst, *mode, &theme) {
                     Ok(v) => v,
-                    Err(e) => panic!(
-                        "{}: Failed to convert utf-8 to string: {e:?}!",
-                        dst.display()
-                    ),
+                    Err(e) => break 'edit Some(Err(e)),
                 };
-
-                let code = read_to_string(&dst).unwrap();
-                let spans = syntax::highlight(&code, extension, &theme);
-                let instructions = insts(spans);
+                if let Err(e) = std::fs::write(&dst, text) {
+                    let message = format!("failed to write {}: {e}", dst.display());
+                    break 'edit Some(Err(io::Error::new(e.kind(), message)));
+                }
+                print_fake_cmd(action.show_status, last_code);
+                write_command(&format!("edit {dst_text}"));
 
                 let mut runtime = Runtime::builder(
                     Document::new("@main"),
@@ -154,65 +444,222 @@ fn main() {
                 let editor_state = Doc::new(dst);
 
                 let current_dir = env::current_dir().expect("Failed to get working directory");
-                env::set_current_dir(&base_path).expect("Failed to set working directory to app root");
-                
+                env::set_current_dir(&base_path)
+                    .expect("Failed to set working directory to app root");
+
                 let (tx, rx) = mpsc::channel();
                 let cid = runtime
-                .register_component("main", "components/index.aml", Editor::new(tx), editor_state)
-                .unwrap();
-            runtime
+                    .register_component(
+                        "main",
+                        "components/index.aml",
+                        Editor::new(tx),
+                        editor_state,
+                    )
+                    .unwrap();
+                runtime
                     .register_component("footer", "components/footer.aml", (), ())
                     .unwrap();
                 runtime
                     .register_component("folder_list", "components/folder_list.aml", (), ())
                     .unwrap();
-                
-                run_editor(cid, runtime.finish().expect("Failed to build runtime"), rx, instructions);
-                
+
+                run_editor(
+                    cid,
+                    runtime.finish().expect("Failed to build runtime"),
+                    rx,
+                    instructions,
+                );
+
                 env::set_current_dir(current_dir).expect("Failed to restore working directory");
+                None
+            }
+        };
+
+        // the status of actions that aren't seen isn't shown in the next prompt
+        let hidden = matches!(
+            action.kind,
+            ActionKind::RunCommandQuiet(_)
+                | ActionKind::ChangeDirQuiet(_)
+                | ActionKind::RunBackground(..)
+                | ActionKind::KillBackground(_)
+                | ActionKind::WaitBackground(..)
+                | ActionKind::WaitFor(..)
+        );
+        let failure = match result {
+            Some(Ok(output)) => {
+                let code = exit_code(output.status);
+                if !hidden {
+                    last_code = code;
+                }
+                (code != 0).then(|| format!("exited with status {code}").into_boxed_str())
+            }
+            Some(Err(e)) => {
+                // errors might name paths and values that are redacted from everything else
+                let e = redact::redact_str(&e.to_string());
+                eprintln!("{}", ContentStyle::default().red().apply(&e));
+                if !hidden {
+                    last_code = 127;
+                }
+                Some(e.into_boxed_str())
+            }
+            None => None,
+        };
+        let shows_prompt = matches!(
+            action.kind,
+            ActionKind::ChangeDir(..)
+                | ActionKind::RunCommand(_)
+                | ActionKind::RunCommandWithInput(..)
+                | ActionKind::FakeCommand(..)
+        );
+
+        if let Some(reason) = failure {
+            match action.on_error {
+                OnError::Continue => failures.push((index, reason)),
+                OnError::Stop => {
+                    failures.push((index, reason));
+                    break;
+                }
+                OnError::Prompt => match prompt_failure(index, &reason) {
+                    FailureChoice::Retry => continue,
+                    // the answer already was the key press to go on
+                    FailureChoice::Skip => {
+                        failures.push((index, reason));
+                        index += 1;
+                        continue;
+                    }
+                    FailureChoice::Quit => {
+                        failures.push((index, reason));
+                        break;
+                    }
+                },
             }
         }
+        if shows_prompt {
+            wait_for_input();
+        }
 
         std::thread::sleep(Duration::from_millis(50));
+        index += 1;
     }
 
     sleep_between_characters();
     sleep_between_characters();
+    drop(background);
+    if let Some(sandbox) = sandbox {
+        // don't remove the directory scammed is in
+        _ = env::set_current_dir(&base_path);
+        drop(sandbox);
+    }
+
+    if !failures.is_empty() {
+        print_failures(&actions, &failures);
+        std::process::exit(1);
+    }
+}
+
+/// The exit code of a process, or `128 + signal` like a shell reports it if the
+/// process was killed.
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+enum FailureChoice {
+    Retry,
+    Skip,
+    Quit,
+}
+
+/// Asks whether to retry, skip or quit after action `index` failed.
+fn prompt_failure(index: usize, reason: &str) -> FailureChoice {
+    print!(
+        "{}",
+        ContentStyle::default().red().apply(format!(
+            "action {} failed ({reason}): [r]etry, [s]kip or [q]uit? ",
+            index + 1
+        ))
+    );
+    _ = std::io::stdout().flush();
+    loop {
+        let (choice, answer) = match read_key() {
+            b'r' | b'R' => (FailureChoice::Retry, "retry"),
+            b's' | b'S' => (FailureChoice::Skip, "skip"),
+            // ctrl-c quits as well
+            b'q' | b'Q' | 3 => (FailureChoice::Quit, "quit"),
+            _ => continue,
+        };
+        println!("{answer}");
+        return choice;
+    }
+}
+
+fn print_failures(actions: &[Action], failures: &[(usize, Box<str>)]) {
+    let error = ContentStyle::default().red().bold();
+    match failures.len() {
+        1 => eprintln!("\n{}", error.apply("1 action failed:")),
+        n => eprintln!("\n{}", error.apply(format!("{n} actions failed:"))),
+    }
+    for (index, reason) in failures {
+        let action = &actions[*index];
+        let failure = format!(
+            "  action {} (line {}) `{action}`: {reason}",
+            index + 1,
+            action.line
+        );
+        eprintln!("{}", redact::redact_str(&failure));
+    }
 }
 
 fn wait_for_input() {
-    let mut input = std::io::stdin();
+    read_key();
+}
+
+/// Waits for a key press and returns its first byte.
+fn read_key() -> u8 {
     enable_raw_mode().unwrap();
 
     loop {
         let mut buf = [0u8; 4];
-        let Ok(num_read) = input.read(&mut buf) else {
+        // read directly, as `Stdin` would buffer key presses that should be discarded
+        let num_read =
+            unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        let Ok(num_read) = usize::try_from(num_read) else {
             continue;
         };
         if num_read > 0 {
             for n in &buf[0..num_read] {
                 if *n != 0 {
                     disable_raw_mode().unwrap();
-                    return;
+                    return *n;
                 }
             }
         }
     }
 }
 
-fn cd(path: &str) {
+fn cd(path: &str) -> io::Result<()> {
+    let dir = resolve_path(&env::current_dir()?, path);
+    env::set_current_dir(&dir).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to change directory to {}: {e}", dir.display()),
+        )
+    })
+}
+
+/// Resolves `path` the way a shell would when the working directory is `base`.
+fn resolve_path(base: &Path, path: &str) -> PathBuf {
     if path.starts_with('/') {
-        env::set_current_dir(PathBuf::from(path)).expect("Failed to set current dir");
-    } else if path.starts_with('~') {
+        PathBuf::from(path)
+    } else if let Some(rest) = path.strip_prefix('~') {
         let Some(homedir) = home_dir() else {
             panic!("Could not acquire home directory")
         };
-        env::set_current_dir(homedir.join(path)).expect("Failed to set current dir");
+        homedir.join(rest.trim_start_matches('/'))
     } else {
-        let Ok(dir) = env::current_dir() else {
-            panic!("Could not acquire current directory")
-        };
-        env::set_current_dir(dir.join(path)).expect("Failed to set current dir");
+        base.join(path)
     }
 }
 
@@ -235,15 +682,26 @@ impl Display for FakeCmdPrinter {
     }
 }
 
-fn print_fake_cmd() {
-    print!("{}", ContentStyle::default().green().apply(FakeCmdPrinter));
+/// Prints the prompt, preceded by `last_code` if it is a failure and `show_status`
+/// asks for it.
+fn print_fake_cmd(show_status: ShowStatus, last_code: i32) {
+    let failed = ContentStyle::default().red();
+    match show_status {
+        _ if last_code == 0 => (),
+        ShowStatus::Off => (),
+        ShowStatus::Code => print!("{}", failed.apply(format!("[{last_code}] "))),
+        ShowStatus::Mark => print!("{}", failed.apply("✗ ")),
+    }
+    let prompt = redact::redact_str(&FakeCmdPrinter.to_string());
+    print!("{}", ContentStyle::default().green().apply(prompt));
     _ = std::io::stdout().flush();
 }
 
-fn write_command<'a>(mut cmd: impl Iterator<Item = &'a str>) {
-    let Some(cmd_name) = cmd.next() else {
-        return;
-    };
+/// Types out a command as it was written. Lines continued with `\` are typed
+/// after a continuation prompt.
+fn write_command(cmd: &str) {
+    let name_end = cmd.find(char::is_whitespace).unwrap_or(cmd.len());
+    let (cmd_name, args) = cmd.split_at(name_end);
 
     print!("\x1b[{}m", Colored::ForegroundColor(Color::Cyan));
     write_str_typing(cmd_name);
@@ -251,17 +709,69 @@ fn write_command<'a>(mut cmd: impl Iterator<Item = &'a str>) {
     print!("\x1b[{}m", Colored::ForegroundColor(Color::Reset));
     let mut output = std::io::stdout();
     _ = output.flush();
-    for arg in cmd {
-        print!(" ");
-        _ = output.flush();
-        sleep_between_characters();
-        write_str_typing(arg);
+    for (index, line) in args.split('\n').enumerate() {
+        if index != 0 {
+            print!("\n> ");
+            _ = output.flush();
+            sleep_between_characters();
+        }
+        write_str_typing(line);
     }
     print!("\n");
     _ = std::io::stdout().flush();
 }
 
+/// Types out the lines of a heredoc after continuation prompts, like a shell shows them.
+fn write_heredoc(heredoc: &Heredoc) {
+    let mut output = std::io::stdout();
+    for line in heredoc.body.lines().chain([&*heredoc.delimiter]) {
+        print!("> ");
+        _ = output.flush();
+        sleep_between_characters();
+        write_str_typing(line);
+        println!();
+        _ = output.flush();
+    }
+}
+
+/// Prints canned output one line at a time, as if a command was printing it.
+fn print_fake_output(output: &str, delay: Duration) {
+    let mut stdout = std::io::stdout();
+    for line in output.lines() {
+        _ = writeln!(stdout, "{line}");
+        _ = stdout.flush();
+        thread::sleep(delay);
+    }
+}
+
 fn write_str_typing(value: &str) {
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        let (typed, correct) = match human::typo(c, chars.peek().copied()) {
+            None => {
+                type_str(&c.to_string());
+                continue;
+            }
+            Some(Typo::Neighbour(wrong)) => (wrong.to_string(), c.to_string()),
+            Some(Typo::Transpose(next)) => {
+                chars.next();
+                (format!("{next}{c}"), format!("{c}{next}"))
+            }
+        };
+        type_str(&typed);
+        thread::sleep(Duration::from_millis(human::notice_delay()));
+        for _ in typed.chars() {
+            // moves back, blanks the character and moves back onto the blank
+            print!("\x08 \x08");
+            _ = std::io::stdout().flush();
+            sleep_between_characters();
+        }
+        type_str(&correct);
+    }
+}
+
+/// Prints `value` one key press at a time.
+fn type_str(value: &str) {
     let mut output = std::io::stdout();
     for c in value.chars() {
         let mut data = [0u8; 4];
@@ -280,12 +790,22 @@ fn run_editor(
     let emitter = runtime.emitter();
 
     thread::spawn(move || {
+        let mut speed = 1.0;
         for i in instructions {
             if let Instruction::Pause(ms) = i {
                 thread::sleep(Duration::from_millis(ms));
                 continue;
             }
 
+            if let Instruction::SetSpeed(new_speed) = i {
+                speed = new_speed;
+                continue;
+            }
+
+            if let Instruction::FileOnly(_) = i {
+                continue;
+            }
+
             if let Instruction::Wait = i {
                 _ = emitter.emit(cid, i);
                 _ = rx.recv();
@@ -301,7 +821,7 @@ fn run_editor(
             }
             _ = rx.try_recv();
 
-            sleep_between_characters();
+            thread::sleep(typing_delay().div_f64(speed));
             _ = emitter.emit(cid, i);
         }
 
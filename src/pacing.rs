@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::io::{self, Stdout};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossterm::style::{ContentStyle, Stylize};
+
+use crate::redact::{Redacting, HOLD_BACK};
+
+/// How the output of a command is shown, as set by `set output_rate` and
+/// `set output_lines`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pacing {
+    // lines shown per second, as fast as they are printed if `None`
+    pub rate: Option<u32>,
+    // the most lines shown, the middle of longer output is left out
+    pub max_lines: Option<usize>,
+}
+
+impl Pacing {
+    fn is_off(&self) -> bool {
+        self.rate.is_none() && self.max_lines.is_none()
+    }
+}
+
+impl Display for Pacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.rate, self.max_lines) {
+            (Some(rate), Some(lines)) => {
+                write!(
+                    f,
+                    "at {rate} line(s) per second, collapsed to {lines} line(s)"
+                )
+            }
+            (Some(rate), None) => write!(f, "at {rate} line(s) per second"),
+            (None, Some(lines)) => write!(f, "collapsed to {lines} line(s)"),
+            (None, None) => f.write_str("as it is printed"),
+        }
+    }
+}
+
+/// Shows output written to it in parts line by line at the pace of a `Pacing`,
+/// leaving out the middle of it once it has more lines than allowed. The lines
+/// are queued and shown by calling `pump` regularly.
+pub struct Paced {
+    out: Redacting<Stdout>,
+    pacing: Pacing,
+    // complete lines waiting to be shown
+    queue: VecDeque<Vec<u8>>,
+    // the incomplete last line
+    partial: Vec<u8>,
+    // whether the start of `partial` was shown already
+    continuing: bool,
+    // lines shown or queued from the start of the output
+    head: usize,
+    // the last lines once the output is longer than the head
+    tail: VecDeque<Vec<u8>>,
+    omitted: usize,
+    next_line: Instant,
+}
+
+impl Paced {
+    pub fn new(pacing: Pacing) -> Self {
+        Self {
+            out: Redacting::new(io::stdout()),
+            pacing,
+            queue: VecDeque::new(),
+            partial: vec![],
+            continuing: false,
+            head: 0,
+            tail: VecDeque::new(),
+            omitted: 0,
+            next_line: Instant::now(),
+        }
+    }
+
+    pub fn write(&mut self, output: &[u8]) -> io::Result<()> {
+        if self.pacing.is_off() {
+            return self.out.write(output);
+        }
+
+        self.partial.extend_from_slice(output);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let rest = self.partial.split_off(end + 1);
+            let line = std::mem::replace(&mut self.partial, rest);
+            if std::mem::take(&mut self.continuing) {
+                // the rest of a line that was shown while it was incomplete
+                self.out.write(&line)?;
+            } else {
+                self.push(line);
+            }
+        }
+        self.pump()
+    }
+
+    /// Queues a line if it is part of the head, or keeps it as part of the tail.
+    fn push(&mut self, line: Vec<u8>) {
+        let max_lines = self.pacing.max_lines.unwrap_or(usize::MAX);
+        // the head gets the extra line if the lines can't be split evenly
+        if self.head < max_lines - max_lines / 2 {
+            if self.queue.is_empty() {
+                // don't make up for the time nothing was printed
+                self.next_line = self.next_line.max(Instant::now());
+            }
+            self.head += 1;
+            self.queue.push_back(line);
+            return;
+        }
+        self.tail.push_back(line);
+        if self.tail.len() > max_lines / 2 {
+            self.tail.pop_front();
+            self.omitted += 1;
+        }
+    }
+
+    /// Shows the queued lines whose time has come.
+    pub fn pump(&mut self) -> io::Result<()> {
+        let Some(rate) = self.pacing.rate else {
+            while let Some(line) = self.queue.pop_front() {
+                self.out.write(&line)?;
+            }
+            return Ok(());
+        };
+        let now = Instant::now();
+        while now >= self.next_line {
+            let Some(line) = self.queue.pop_front() else {
+                break;
+            };
+            self.out.write(&line)?;
+            self.next_line += Duration::from_secs(1) / rate;
+        }
+        Ok(())
+    }
+
+    /// How long until the next queued line is due, if there is one.
+    pub fn until_next(&self) -> Option<Duration> {
+        match self.queue.is_empty() {
+            true => None,
+            false => Some(self.next_line.saturating_duration_since(Instant::now())),
+        }
+    }
+
+    /// Shows the incomplete last line once everything before it was shown, as it
+    /// might be a prompt waiting for input. Nothing is shown while the middle of
+    /// the output is left out.
+    pub fn flush_pending(&mut self) -> io::Result<()> {
+        if self.pacing.is_off() {
+            return self.out.flush_pending();
+        }
+        let max_lines = self.pacing.max_lines.unwrap_or(usize::MAX);
+        let in_head = self.continuing || self.head < max_lines - max_lines / 2;
+        if !self.queue.is_empty() || self.partial.is_empty() || !in_head {
+            return Ok(());
+        }
+        if !self.continuing {
+            self.head += 1;
+            self.continuing = true;
+        }
+        let partial = std::mem::take(&mut self.partial);
+        self.out.write(&partial)?;
+        self.out.flush_pending()
+    }
+
+    /// Shows everything left once the output is complete, blocking until the
+    /// last line is due.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.pacing.is_off() {
+            return self.out.flush_pending();
+        }
+        let partial = std::mem::take(&mut self.partial);
+        if std::mem::take(&mut self.continuing) {
+            self.out.write(&partial)?;
+        } else if !partial.is_empty() {
+            self.push(partial);
+        }
+
+        if self.queue.is_empty() {
+            self.next_line = self.next_line.max(Instant::now());
+        }
+        if self.omitted > 0 {
+            let marker = format!("... ({} lines omitted)", self.omitted);
+            let marker = ContentStyle::default().dark_grey().apply(marker);
+            self.queue.push_back(format!("{marker}\r\n").into_bytes());
+        }
+        self.queue.extend(std::mem::take(&mut self.tail));
+        while let Some(wait) = self.until_next() {
+            thread::sleep(wait);
+            self.pump()?;
+        }
+        self.out.flush_pending()
+    }
+
+    /// Waits for `duration` while showing the lines that become due.
+    pub fn wait(&mut self, duration: Duration) -> io::Result<()> {
+        let end = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            if self.queue.is_empty() && end - now >= HOLD_BACK {
+                self.flush_pending()?;
+            }
+            thread::sleep(
+                self.until_next()
+                    .map_or(end - now, |next| next.min(end - now)),
+            );
+            self.pump()?;
+        }
+    }
+}
@@ -2,70 +2,265 @@ use std::cmp::Ordering;
 
 use anathema::state::Hex;
 
+use crate::directive::{self, Directive, DirectiveError};
+use crate::human::{self, Typo};
 use crate::instruction::Instruction;
-use crate::syntax::{Span, Line};
+use crate::syntax::{Comment, Line, Span};
 
 pub struct Parser<'a> {
     lines: Box<[Line<'a>]>,
+    // how comments, and with them directives, are written in the lines
+    comment: Comment,
     instructions: Vec<Instruction>,
-    foreground: Hex,
+    // the last foreground that was set, if any
+    foreground: Option<Hex>,
+    // the next line goes on from the cursor, as an edit moved it
+    continuing: bool,
+    // makes typos and corrects them
+    human: bool,
+    // where the instructions of an open `[INSTANT]` start, and its line
+    instant: Option<(usize, usize)>,
+    // typed lines are inserted above the cursor, after a `[GOTO]`
+    inserting: bool,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lines: Box<[Line<'a>]>) -> Self {
+    pub fn new(lines: Box<[Line<'a>]>, comment: Comment) -> Self {
         Self {
             lines,
+            comment,
             instructions: vec![],
-            foreground: Hex::BLACK,
+            foreground: None,
+            continuing: false,
+            human: false,
+            instant: None,
+            inserting: false,
         }
     }
 
-    pub fn instructions(mut self) -> Vec<Instruction> {
-        let lines = std::mem::take(&mut self.lines);
+    /// Makes typos and corrects them while typing, if `--typos` asked for them.
+    pub fn human(mut self) -> Self {
+        self.human = true;
+        self
+    }
 
-        for line in &*lines {
-            let mut line_start = 0;
+    /// The instructions that type the lines, following the directives in them.
+    /// Fails on the first directive that doesn't exist or can't be used where it is.
+    pub fn instructions(mut self) -> Result<Vec<Instruction>, DirectiveError> {
+        let lines = std::mem::take(&mut self.lines);
+        // the line of an open `[SKIP]`
+        let mut skip = None;
 
-            if line.head.src.starts_with("//") {
-                if line.tail[0].src.contains("[WAIT]") {
-                    self.instructions.push(Instruction::Wait);
+        for (index, line) in lines.iter().enumerate() {
+            let error = |message: String| DirectiveError {
+                line: index + 1,
+                message,
+            };
+            let directive = match directive::parse(&line.text(), self.comment) {
+                Some(directive) => directive.map_err(error)?,
+                None if skip.is_some() => {
+                    let text = line.text();
+                    let text = text.trim_end_matches(['\r', '\n']);
+                    self.instructions.push(Instruction::FileOnly(text.into()));
+                    continue;
+                }
+                None => {
+                    self.push_line(line);
                     continue;
                 }
+            };
+            if skip.is_some() {
+                if directive == Directive::EndSkip {
+                    skip = None;
+                }
+                continue;
+            }
+            if self.instant.is_some()
+                && matches!(
+                    directive,
+                    Directive::Wait | Directive::Pause(_) | Directive::Speed(_)
+                )
+            {
+                return Err(error("pauses can't be used inside `[INSTANT]`".into()));
             }
 
-            let (count, src, bold) = line.head.take_space();
-            if let Some(x) = count {
-                self.instructions.push(Instruction::SetX(x));
-                line_start = x;
-            } else {
-                self.instructions.push(Instruction::SetX(0));
+            match directive {
+                Directive::Wait => self.instructions.push(Instruction::Wait),
+                Directive::Pause(duration) => self
+                    .instructions
+                    .push(Instruction::Pause(duration.as_millis() as u64)),
+                Directive::Speed(speed) => self.instructions.push(Instruction::SetSpeed(speed)),
+                Directive::Instant if self.instant.is_none() => {
+                    self.instant = Some((self.instructions.len(), index + 1))
+                }
+                Directive::Instant => return Err(error("`[INSTANT]` is already open".into())),
+                Directive::EndInstant => {
+                    let Some((start, _)) = self.instant.take() else {
+                        return Err(error("`[/INSTANT]` without an `[INSTANT]`".into()));
+                    };
+                    let instant = self.instructions.split_off(start);
+                    self.instructions.push(Instruction::Instant(instant));
+                }
+                Directive::Goto(y) => {
+                    self.instructions.push(Instruction::MoveCursor(0, y));
+                    self.inserting = true;
+                    self.continuing = false;
+                }
+                Directive::Highlight(lines) => {
+                    self.instructions.push(Instruction::Highlight(lines))
+                }
+                Directive::Skip => skip = Some(index + 1),
+                Directive::EndSkip => return Err(error("`[/SKIP]` without a `[SKIP]`".into())),
+                edit => {
+                    self.drop_line_break();
+                    self.instructions.extend(edit_instructions(edit));
+                    self.continuing = true;
+                }
             }
+        }
 
-            self.set_foreground(&line.head);
-            self.push_chars(src, bold, line_start);
+        if let Some(line) = skip {
+            return Err(DirectiveError {
+                line,
+                message: "`[SKIP]` is never closed".into(),
+            });
+        }
+        if let Some((_, line)) = self.instant {
+            return Err(DirectiveError {
+                line,
+                message: "`[INSTANT]` is never closed".into(),
+            });
+        }
+        Ok(self.instructions)
+    }
 
+    /// Drops the line break after the text typed so far, so an edit starts at
+    /// its end rather than on the next line.
+    fn drop_line_break(&mut self) {
+        let last = self.instructions.iter().rposition(|inst| {
+            !matches!(
+                inst,
+                Instruction::Wait
+                    | Instruction::Pause(_)
+                    | Instruction::SetSpeed(_)
+                    | Instruction::Highlight(_)
+            )
+        });
+        if let Some(index) = last {
+            if let Instruction::Newline { .. } = self.instructions[index] {
+                self.instructions.remove(index);
+            }
+        }
+    }
+
+    /// The instructions that type `line` from the start of the cursor's row,
+    /// leaving the cursor at its end.
+    pub fn line_instructions(&mut self, line: &Line<'_>) -> Vec<Instruction> {
+        // the row might be anywhere, after anything else was typed
+        self.foreground = None;
+        self.push_line(line);
+        let mut instructions = std::mem::take(&mut self.instructions);
+        instructions.retain(|inst| !matches!(inst, Instruction::Newline { .. }));
+        instructions
+    }
+
+    fn push_line(&mut self, line: &Line<'_>) {
+        let mut line_start = 0;
+
+        let (count, src, bold) = line.head.take_space();
+        if std::mem::take(&mut self.continuing) {
+            // the indentation is typed from the cursor as well
+            self.set_foreground(&line.head);
+            self.push_chars(line.head.src, bold, count.unwrap_or(0));
             for span in &*line.tail {
                 self.set_foreground(span);
-                self.push_chars(span.src, span.bold, line_start);
+                self.push_chars(span.src, span.bold, count.unwrap_or(0));
             }
+            return;
+        }
+        if self.inserting {
+            self.instructions.push(Instruction::InsertLine);
+        }
+        if let Some(x) = count {
+            self.instructions.push(Instruction::SetX(x));
+            line_start = x;
+        } else {
+            self.instructions.push(Instruction::SetX(0));
         }
 
-        self.instructions
+        self.set_foreground(&line.head);
+        self.push_chars(src, bold, line_start);
+
+        for span in &*line.tail {
+            self.set_foreground(span);
+            self.push_chars(span.src, span.bold, line_start);
+        }
     }
 
     fn set_foreground(&mut self, span: &Span) {
-        if span.fg != self.foreground {
+        if Some(span.fg) != self.foreground {
             self.instructions.push(Instruction::SetForeground(span.fg));
-            self.foreground = span.fg;
+            self.foreground = Some(span.fg);
         }
     }
 
     fn push_chars(&mut self, src: &str, bold: bool, line_start: i32) {
-        for c in src.chars() {
-            match c {
-                '\n' => self.instructions.push(Instruction::Newline { x: line_start }),
-                c => self.instructions.push(Instruction::Type(c, bold)),
+        let mut chars = src.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c == '\n' {
+                self.instructions
+                    .push(Instruction::Newline { x: line_start });
+                continue;
             }
+            // typos aren't made in text that is shown at once
+            let typo = match self.human && self.instant.is_none() {
+                true => human::typo(c, chars.peek().copied()),
+                false => None,
+            };
+            let (typed, correct) = match typo {
+                None => {
+                    self.instructions.push(Instruction::Type(c, bold));
+                    continue;
+                }
+                Some(Typo::Neighbour(wrong)) => (vec![wrong], vec![c]),
+                Some(Typo::Transpose(next)) => {
+                    chars.next();
+                    (vec![next, c], vec![c, next])
+                }
+            };
+            self.instructions
+                .extend(typed.iter().map(|&c| Instruction::Type(c, bold)));
+            self.instructions
+                .push(Instruction::Pause(human::notice_delay()));
+            self.instructions
+                .extend(typed.iter().map(|_| Instruction::Backspace));
+            self.instructions
+                .extend(correct.into_iter().map(|c| Instruction::Type(c, bold)));
         }
     }
 }
+
+/// The instructions of an edit directive like `//[BACKSPACE 3]`.
+///
+/// - `//[BACKSPACE n]` removes the last `n` characters, joining lines at their start
+/// - `//[DELETE n]` removes `n` characters after the cursor
+/// - `//[DELETE LINE]` removes the line the cursor is on
+/// - `//[DELETE 2:5-3:1]` removes the text from line 2, column 5 through line 3, column 1
+/// - `//[JOIN]` appends the next line to the cursor's line
+///
+/// The edit starts at the end of the text typed so far, and the line after it is
+/// typed from wherever the edit left the cursor.
+fn edit_instructions(edit: Directive) -> Vec<Instruction> {
+    match edit {
+        Directive::Backspace(count) => vec![Instruction::Backspace; count],
+        Directive::Delete(count) => vec![Instruction::DeleteForward; count],
+        Directive::DeleteLine => vec![Instruction::DeleteLine],
+        // the last column is removed as well
+        Directive::DeleteRange(from, to) => vec![Instruction::DeleteRange {
+            from,
+            to: (to.0 + 1, to.1),
+        }],
+        Directive::Join => vec![Instruction::JoinLines],
+        _ => unreachable!("{edit:?} isn't an edit"),
+    }
+}
@@ -0,0 +1,362 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::actions::Expectation;
+use crate::command::CommandOutput;
+use crate::pacing::{Paced, Pacing};
+use crate::typing_delay;
+
+/// How long to wait for output or input before checking whether the child exited.
+const POLL_TIMEOUT_MS: i32 = 50;
+/// How long to keep reading the output that is left once the child exited, as a
+/// process it left running might keep on printing.
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A pseudo-terminal that a command can run in, so it behaves the same as it
+/// would in a normal terminal session.
+pub struct Pty {
+    master: File,
+    slave: File,
+}
+
+impl Pty {
+    /// Opens a pseudo-terminal with the size of the current terminal.
+    pub fn open() -> io::Result<Self> {
+        let (cols, rows) = crossterm::terminal::size().unwrap_or((80, 24));
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+
+        let mut master: RawFd = -1;
+        let mut slave: RawFd = -1;
+        let result = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                &size,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `openpty` succeeded, so both descriptors are open and owned by us
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+        set_cloexec(&master)?;
+        set_cloexec(&slave)?;
+        Ok(Self { master, slave })
+    }
+
+    /// Runs `cmd` with the pty as its stdio and controlling terminal.
+    pub fn spawn(&self, cmd: &mut Command) -> io::Result<Child> {
+        cmd.stdin(Stdio::from(self.slave.try_clone()?));
+        cmd.stdout(Stdio::from(self.slave.try_clone()?));
+        cmd.stderr(Stdio::from(self.slave.try_clone()?));
+        // SAFETY: only async-signal-safe functions are called between fork and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn();
+        // drop the copies of the pty `cmd` holds on to
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::null());
+        cmd.stderr(Stdio::null());
+        child
+    }
+
+    /// Copies the output of the pty to stdout and stdin to the pty until `child`
+    /// exits, returning everything it printed. Stdin should be in raw mode, so
+    /// every key press reaches the child.
+    ///
+    /// Every expectation's answer is typed once its prompt shows up in the output.
+    /// Fails if a prompt doesn't show up in time or before the child exits.
+    pub fn relay(
+        self,
+        child: &mut Child,
+        expectations: &[Expectation],
+        pacing: Pacing,
+    ) -> io::Result<CommandOutput> {
+        // only the child may keep the pty open, so reading fails once it is done
+        drop(self.slave);
+        let mut master = self.master;
+        let mut stdout = Paced::new(pacing);
+        let mut buf = [0u8; 4096];
+        // stops being polled once it is closed
+        let mut stdin_fd = libc::STDIN_FILENO;
+        let mut answers = Answers::new(expectations);
+        let mut output = vec![];
+        let mut timing = vec![];
+        let start = Instant::now();
+        // how the child exited and until when the output left is read
+        let mut exited = None;
+
+        loop {
+            if let Err(e) = answers.type_next(&mut master) {
+                _ = child.kill();
+                _ = child.wait();
+                return Err(e);
+            }
+            let timeout = match exited {
+                // only what is there already is read
+                Some(_) => 0,
+                None => [answers.until_next_key(), stdout.until_next()]
+                    .into_iter()
+                    .flatten()
+                    .fold(POLL_TIMEOUT_MS, |timeout, wait| {
+                        timeout.min(wait.as_millis() as i32)
+                    }),
+            };
+
+            let mut fds = [
+                libc::pollfd {
+                    fd: master.as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+                libc::pollfd {
+                    fd: stdin_fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                },
+            ];
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) };
+            if ready < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            stdout.pump()?;
+            if ready == 0 {
+                if exited.is_some() {
+                    break;
+                }
+                // nothing else arrived, the rest of the line might not come soon
+                stdout.flush_pending()?;
+            }
+
+            if fds[0].revents != 0 {
+                match master.read(&mut buf) {
+                    // the child closed the pty
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        stdout.write(&buf[..n])?;
+                        answers.output(&buf[..n]);
+                        output.extend_from_slice(&buf[..n]);
+                        timing.push((start.elapsed(), output.len()));
+                    }
+                }
+            }
+            if fds[1].revents & libc::POLLIN != 0 {
+                // read directly, as `Stdin` would buffer input the child never sees
+                let n = unsafe { libc::read(stdin_fd, buf.as_mut_ptr().cast(), buf.len()) };
+                if n > 0 {
+                    master.write_all(&buf[..n as usize])?;
+                } else {
+                    stdin_fd = -1;
+                }
+            }
+
+            // a process the child left running in the background might keep the pty
+            // open, and keep it readable if it keeps on printing
+            match exited {
+                None => {
+                    if let Some(status) = child.try_wait()? {
+                        exited = Some((status, Instant::now() + DRAIN_TIMEOUT));
+                    }
+                }
+                Some((_, deadline)) if Instant::now() >= deadline => break,
+                Some(_) => (),
+            }
+        }
+
+        let status = match exited {
+            Some((status, _)) => status,
+            None => child.wait()?,
+        };
+        stdout.finish()?;
+        answers.finish()?;
+        Ok(CommandOutput {
+            status,
+            output,
+            timing,
+        })
+    }
+}
+
+/// Watches the output of a command for the prompts of its expectations and types
+/// their answers.
+struct Answers<'a> {
+    expectations: &'a [Expectation],
+    // index of the expectation whose prompt is waited for or whose answer is typed
+    current: usize,
+    // output since the last prompt, without escape sequences
+    output: Vec<u8>,
+    escapes: EscapeState,
+    // the rest of the current answer, once its prompt showed up
+    typing: Option<std::vec::IntoIter<char>>,
+    next_key: Instant,
+    // when the current prompt has to have shown up
+    deadline: Instant,
+}
+
+impl<'a> Answers<'a> {
+    fn new(expectations: &'a [Expectation]) -> Self {
+        let now = Instant::now();
+        Self {
+            expectations,
+            current: 0,
+            output: vec![],
+            escapes: EscapeState::Text,
+            typing: None,
+            next_key: now,
+            deadline: now + expectations.first().map_or(Duration::ZERO, |e| e.timeout),
+        }
+    }
+
+    fn output(&mut self, bytes: &[u8]) {
+        if self.current == self.expectations.len() {
+            return;
+        }
+        for &byte in bytes {
+            if self.escapes.strip(byte) {
+                self.output.push(byte);
+            }
+        }
+        self.find_prompt();
+    }
+
+    fn find_prompt(&mut self) {
+        if self.typing.is_some() {
+            return;
+        }
+        let Some(expectation) = self.expectations.get(self.current) else {
+            return;
+        };
+        let prompt = expectation.prompt.as_bytes();
+        match self
+            .output
+            .windows(prompt.len())
+            .position(|window| window == prompt)
+        {
+            Some(start) => {
+                self.output.drain(..start + prompt.len());
+                let mut answer = expectation.answer.chars().collect::<Vec<_>>();
+                answer.push('\r');
+                self.typing = Some(answer.into_iter());
+                self.next_key = Instant::now() + typing_delay();
+            }
+            // only the end could still turn into the prompt
+            None => {
+                let keep = self.output.len().min(prompt.len());
+                self.output.drain(..self.output.len() - keep);
+            }
+        }
+    }
+
+    /// Types the next key of the answer if it is time to, or fails if the
+    /// prompt didn't show up in time.
+    fn type_next(&mut self, master: &mut File) -> io::Result<()> {
+        let now = Instant::now();
+        let Some(typing) = &mut self.typing else {
+            return match self.expectations.get(self.current) {
+                Some(expectation) if now >= self.deadline => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "timed out after {:?} waiting for `{}`",
+                        expectation.timeout, expectation.prompt
+                    ),
+                )),
+                _ => Ok(()),
+            };
+        };
+        if now < self.next_key {
+            return Ok(());
+        }
+
+        if let Some(c) = typing.next() {
+            master.write_all(c.encode_utf8(&mut [0; 4]).as_bytes())?;
+            self.next_key = now + typing_delay();
+        }
+        if typing.as_slice().is_empty() {
+            self.typing = None;
+            self.current += 1;
+            if let Some(expectation) = self.expectations.get(self.current) {
+                self.deadline = now + expectation.timeout;
+            }
+            // the next prompt might already have been printed
+            self.find_prompt();
+        }
+        Ok(())
+    }
+
+    fn until_next_key(&self) -> Option<Duration> {
+        let until = || self.next_key.saturating_duration_since(Instant::now());
+        self.typing.is_some().then(until)
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        match self.expectations.get(self.current) {
+            Some(expectation) => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("command exited before showing `{}`", expectation.prompt),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Tracks escape sequences in the output, so prompts can be matched even
+/// when the command colors them.
+enum EscapeState {
+    Text,
+    // after an `ESC`
+    Escape,
+    // inside `ESC [`, until a final byte
+    Csi,
+    // inside `ESC ]`, until `BEL` or `ESC \`
+    Osc,
+    OscEscape,
+}
+
+impl EscapeState {
+    /// Returns whether `byte` is part of the text rather than an escape sequence.
+    fn strip(&mut self, byte: u8) -> bool {
+        *self = match (&self, byte) {
+            (Self::Text, 0x1b) => Self::Escape,
+            (Self::Text, _) => return true,
+            (Self::Escape, b'[') => Self::Csi,
+            (Self::Escape, b']') => Self::Osc,
+            (Self::Escape, _) => Self::Text,
+            (Self::Csi, 0x40..=0x7e) => Self::Text,
+            (Self::Csi, _) => Self::Csi,
+            (Self::Osc, 0x07) => Self::Text,
+            (Self::Osc, 0x1b) => Self::OscEscape,
+            (Self::Osc, _) => Self::Osc,
+            (Self::OscEscape, _) => Self::Text,
+        };
+        false
+    }
+}
+
+fn set_cloexec(file: &File) -> io::Result<()> {
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,279 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use crate::command::CommandOutput;
+use crate::pacing::{Paced, Pacing};
+
+/// The first line of every recording, so other files aren't mistaken for one.
+const HEADER: &str = "scammed output 1";
+
+/// Where the output of commands comes from.
+pub enum Outputs {
+    // running the commands
+    Live,
+    // running the commands and storing their output in the directory
+    Record(PathBuf),
+    // playing back the output stored in the directory instead of running the commands
+    Replay(PathBuf),
+}
+
+impl Outputs {
+    /// Runs the command with `run` or plays back its recorded output. `index` and
+    /// `text` identify the command, the played back output is shown at the pace
+    /// of `show` unless it is `None`. Nothing is run when playing back.
+    pub fn run(
+        &self,
+        index: usize,
+        text: &str,
+        show: Option<Pacing>,
+        run: impl FnOnce() -> io::Result<CommandOutput>,
+    ) -> io::Result<CommandOutput> {
+        match self {
+            Self::Live => run(),
+            Self::Record(dir) => {
+                let output = run();
+                save(&path(dir, index), text, &output)?;
+                output
+            }
+            Self::Replay(dir) => {
+                let output = load(&path(dir, index), index, text)?;
+                if let (Ok(output), Some(pacing)) = (&output, show) {
+                    replay(output, pacing)?;
+                }
+                output
+            }
+        }
+    }
+}
+
+fn path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("{}.out", index + 1))
+}
+
+/// Stores the output as the header, the command's text, its wait status and
+/// every part of the output with the time it was printed at. A command that
+/// failed to run is stored as the header, its text and the error.
+fn save(path: &Path, text: &str, output: &io::Result<CommandOutput>) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = io::BufWriter::new(File::create(path)?);
+    writeln!(file, "{HEADER}")?;
+    writeln!(file, "text {}", text.len())?;
+    file.write_all(text.as_bytes())?;
+    writeln!(file)?;
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => {
+            let error = e.to_string();
+            writeln!(file, "error {}", error.len())?;
+            file.write_all(error.as_bytes())?;
+            writeln!(file)?;
+            return file.flush();
+        }
+    };
+    writeln!(file, "status {}", output.status.into_raw())?;
+
+    let mut start = 0;
+    for &(time, end) in &output.timing {
+        writeln!(file, "part {} {}", time.as_micros(), end - start)?;
+        file.write_all(&output.output[start..end])?;
+        writeln!(file)?;
+        start = end;
+    }
+    if start < output.output.len() {
+        writeln!(file, "part 0 {}", output.output.len() - start)?;
+        file.write_all(&output.output[start..])?;
+        writeln!(file)?;
+    }
+    file.flush()
+}
+
+/// Loads a recording. The outer result is whether it could be loaded, the inner
+/// one is the recorded result.
+fn load(path: &Path, index: usize, text: &str) -> io::Result<io::Result<CommandOutput>> {
+    let file = File::open(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "no recorded output for action {} at {}: {e}",
+                index + 1,
+                path.display()
+            ),
+        )
+    })?;
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a valid recording", path.display()),
+        )
+    };
+    let mut file = BufReader::new(file);
+
+    if read_line(&mut file)?.as_deref() != Some(HEADER) {
+        return Err(invalid());
+    }
+    let recorded = read_field(&mut file, "text")
+        .and_then(|len| read_part(&mut file, len))
+        .ok_or_else(invalid)?;
+    if recorded != text.as_bytes() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "the output recorded for action {} is for `{}`, record it again",
+                index + 1,
+                String::from_utf8_lossy(&recorded)
+            ),
+        ));
+    }
+    let line = read_line(&mut file)?.ok_or_else(invalid)?;
+    if let Some(len) = line.strip_prefix("error ").and_then(|len| len.parse().ok()) {
+        let error = read_part(&mut file, len).ok_or_else(invalid)?;
+        let error = String::from_utf8_lossy(&error).into_owned();
+        return Ok(Err(io::Error::other(error)));
+    }
+    let status = line
+        .strip_prefix("status ")
+        .and_then(|status| status.parse().ok())
+        .map(ExitStatus::from_raw)
+        .ok_or_else(invalid)?;
+
+    let mut output = vec![];
+    let mut timing = vec![];
+    while let Some(line) = read_line(&mut file)? {
+        let (time, len) = line
+            .strip_prefix("part ")
+            .and_then(|part| part.split_once(' '))
+            .and_then(|(time, len)| Some((time.parse().ok()?, len.parse().ok()?)))
+            .ok_or_else(invalid)?;
+        output.extend(read_part(&mut file, len).ok_or_else(invalid)?);
+        timing.push((Duration::from_micros(time), output.len()));
+    }
+
+    Ok(Ok(CommandOutput {
+        status,
+        output,
+        timing,
+    }))
+}
+
+fn read_line(file: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if file.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches('\n').to_string()))
+}
+
+/// Reads a `<name> <number>` line.
+fn read_field(file: &mut impl BufRead, name: &str) -> Option<usize> {
+    let line = read_line(file).ok()??;
+    line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok()
+}
+
+/// Reads `len` bytes followed by a newline.
+fn read_part(file: &mut impl Read, len: usize) -> Option<Vec<u8>> {
+    let mut part = vec![0; len + 1];
+    file.read_exact(&mut part).ok()?;
+    (part.pop() == Some(b'\n')).then_some(part)
+}
+
+/// Prints the recorded output with the timing it was printed with originally.
+fn replay(output: &CommandOutput, pacing: Pacing) -> io::Result<()> {
+    let mut stdout = Paced::new(pacing);
+    let start = Instant::now();
+    let mut printed = 0;
+    for &(time, end) in &output.timing {
+        stdout.wait(time.saturating_sub(start.elapsed()))?;
+        stdout.write(&output.output[printed..end])?;
+        printed = end;
+    }
+    stdout.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io;
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::PathBuf;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    use super::{load, save, Outputs};
+    use crate::command::CommandOutput;
+
+    /// A file in a directory of its own, removed along with it.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("scammed-{}-{name}", std::process::id()));
+            Self(dir.join("1.out"))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.parent().unwrap());
+        }
+    }
+
+    fn output() -> CommandOutput {
+        CommandOutput {
+            status: ExitStatus::from_raw(1 << 8),
+            output: b"building\nerror: oh no\n\n".to_vec(),
+            timing: vec![(Duration::from_millis(5), 9), (Duration::from_secs(2), 23)],
+        }
+    }
+
+    #[test]
+    fn loads_the_saved_output() {
+        let file = TempFile::new("output");
+        save(&file.0, "cargo build", &Ok(output())).unwrap();
+        let loaded = load(&file.0, 0, "cargo build").unwrap().unwrap();
+        assert_eq!(loaded.status.code(), Some(1));
+        assert_eq!(loaded.output, output().output);
+        assert_eq!(loaded.timing, output().timing);
+    }
+
+    #[test]
+    fn loads_the_saved_error() {
+        let file = TempFile::new("error");
+        let error = io::Error::other("failed to run `cargo`:\nnot found");
+        save(&file.0, "cargo build", &Err(error)).unwrap();
+        let loaded = load(&file.0, 0, "cargo build").unwrap().unwrap_err();
+        assert_eq!(loaded.to_string(), "failed to run `cargo`:\nnot found");
+    }
+
+    #[test]
+    fn rejects_output_recorded_for_another_command() {
+        let file = TempFile::new("other");
+        save(&file.0, "cargo build", &Ok(output())).unwrap();
+        let error = load(&file.0, 0, "cargo run").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_recordings() {
+        let file = TempFile::new("invalid");
+        fs::create_dir_all(file.0.parent().unwrap()).unwrap();
+        fs::write(&file.0, "building\n").unwrap();
+        let error = load(&file.0, 0, "cargo build").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn runs_nothing_when_playing_back() {
+        let file = TempFile::new("replay");
+        save(&file.0, "cargo build", &Ok(output())).unwrap();
+        let outputs = Outputs::Replay(file.0.parent().unwrap().to_path_buf());
+        let run = || panic!("the command ran");
+        let loaded = outputs.run(0, "cargo build", None, run).unwrap();
+        assert_eq!(loaded.output, output().output);
+    }
+}
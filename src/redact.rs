@@ -0,0 +1,172 @@
+use std::borrow::Cow;
+use std::env::{self, home_dir};
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use regex::bytes::Regex;
+
+/// How long a streamed incomplete line is held back, hoping the rest of a match
+/// follows, before it is shown as it is.
+pub const HOLD_BACK: Duration = Duration::from_millis(50);
+
+/// What API keys of common services look like.
+const KEY_PATTERNS: &[&str] = &[
+    // OpenAI, Anthropic, Stripe
+    r"\b(sk|pk|rk)-[A-Za-z0-9_-]{16,}",
+    r"\b(sk|pk|rk)_(live|test)_[A-Za-z0-9]{16,}",
+    // GitHub
+    r"\bgh[pousr]_[A-Za-z0-9]{20,}",
+    r"\bgithub_pat_[A-Za-z0-9_]{20,}",
+    // GitLab
+    r"\bglpat-[A-Za-z0-9_-]{20,}",
+    // AWS access key ids
+    r"\bAKIA[0-9A-Z]{16}\b",
+    // Slack
+    r"\bxox[abprs]-[A-Za-z0-9-]{10,}",
+    // crates.io
+    r"\bcio[A-Za-z0-9]{32}\b",
+];
+const KEY_MASK: &str = "********";
+
+/// The rules of the scene, installed once it starts.
+static REDACTIONS: OnceLock<Vec<Redaction>> = OnceLock::new();
+
+/// Rewrites every match of `regex` in the output to `replacement`, which can refer
+/// to groups like `$1`.
+#[derive(Debug)]
+pub struct Redaction {
+    regex: Regex,
+    replacement: Box<[u8]>,
+}
+
+impl Redaction {
+    pub fn regex(regex: &str, replacement: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(regex)?,
+            replacement: replacement.as_bytes().into(),
+        })
+    }
+
+    /// Shows the home directory as `~`, if it is known.
+    pub fn home() -> Option<Self> {
+        let home = home_dir()?;
+        let home = home.to_str().filter(|home| home.len() > 1)?;
+        Self::literal(home.trim_end_matches('/'), "~")
+    }
+
+    /// Replaces the name of the current user, if it is known.
+    pub fn username(replacement: &str) -> Option<Self> {
+        let name = env::var("USER").or_else(|_| env::var("LOGNAME")).ok()?;
+        Self::literal(&name, replacement)
+    }
+
+    /// Replaces the hostname of the machine, if it is known.
+    pub fn hostname(replacement: &str) -> Option<Self> {
+        let mut buf = [0u8; 256];
+        if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+            return None;
+        }
+        let name = CStr::from_bytes_until_nul(&buf).ok()?.to_str().ok()?;
+        Self::literal(name, replacement)
+    }
+
+    /// Masks everything that looks like an API key.
+    pub fn keys() -> Self {
+        let regex = KEY_PATTERNS.join("|");
+        Self::regex(&regex, KEY_MASK).expect("key patterns are valid")
+    }
+
+    /// Replaces `text` where it isn't part of a longer word.
+    fn literal(text: &str, replacement: &str) -> Option<Self> {
+        if text.is_empty() {
+            return None;
+        }
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let start = if text.starts_with(is_word) { r"\b" } else { "" };
+        let end = if text.ends_with(is_word) { r"\b" } else { "" };
+        // `$` would be taken as a group reference
+        let replacement = replacement.replace('$', "$$");
+        Self::regex(
+            &format!("{start}{}{end}", regex::escape(text)),
+            &replacement,
+        )
+        .ok()
+    }
+}
+
+/// Sets the rules output is rewritten with for the rest of the run.
+pub fn install(redactions: Vec<Redaction>) {
+    _ = REDACTIONS.set(redactions);
+}
+
+fn redactions() -> &'static [Redaction] {
+    REDACTIONS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Applies every rule to `output`, in the order they were declared in.
+pub fn redact(output: &[u8]) -> Cow<'_, [u8]> {
+    let mut output = Cow::Borrowed(output);
+    for redaction in redactions() {
+        if let Cow::Owned(replaced) = redaction
+            .regex
+            .replace_all(&output, &*redaction.replacement)
+        {
+            output = Cow::Owned(replaced);
+        }
+    }
+    output
+}
+
+pub fn redact_str(text: &str) -> String {
+    String::from_utf8_lossy(&redact(text.as_bytes())).into_owned()
+}
+
+/// Rewrites output that is written to it in parts. The last incomplete line is
+/// held back until the rest of it is written or `flush_pending` is called, so
+/// a match isn't missed because it is split between two parts.
+pub struct Redacting<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Redacting<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: vec![],
+        }
+    }
+
+    pub fn write(&mut self, output: &[u8]) -> io::Result<()> {
+        if redactions().is_empty() {
+            self.inner.write_all(output)?;
+            return self.inner.flush();
+        }
+
+        self.pending.extend_from_slice(output);
+        // progress bars redraw their line with `\r`
+        let Some(end) = self
+            .pending
+            .iter()
+            .rposition(|b| matches!(b, b'\n' | b'\r'))
+        else {
+            return Ok(());
+        };
+        let rest = self.pending.split_off(end + 1);
+        let complete = std::mem::replace(&mut self.pending, rest);
+        self.inner.write_all(&redact(&complete))?;
+        self.inner.flush()
+    }
+
+    /// Writes the held back incomplete line.
+    pub fn flush_pending(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pending = std::mem::take(&mut self.pending);
+        self.inner.write_all(&redact(&pending))?;
+        self.inner.flush()
+    }
+}
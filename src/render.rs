@@ -0,0 +1,259 @@
+use crate::instruction::Instruction;
+
+struct Row {
+    // `None` for the blanks the cursor leaves when it moves past the end
+    chars: Vec<Option<char>>,
+    // false for a line that is written to the file without being shown
+    shown: bool,
+}
+
+/// The editor's text as a plain document, following the instructions the way
+/// the editor does. Positions are in shown rows, like the editor's.
+struct Document {
+    rows: Vec<Row>,
+    x: usize,
+    y: usize,
+}
+
+impl Document {
+    /// The index in `rows` of shown row `y`, or the length of `rows` if there
+    /// are fewer shown rows.
+    fn index(&self, y: usize) -> usize {
+        let mut shown = self.rows.iter().enumerate().filter(|(_, row)| row.shown);
+        shown.nth(y).map_or(self.rows.len(), |(index, _)| index)
+    }
+
+    fn shown_len(&self) -> usize {
+        self.rows.iter().filter(|row| row.shown).count()
+    }
+
+    fn row(&mut self, y: usize) -> Option<&mut Vec<Option<char>>> {
+        let index = self.index(y);
+        self.rows.get_mut(index).map(|row| &mut row.chars)
+    }
+
+    fn line_len(&mut self, y: usize) -> usize {
+        self.row(y).map_or(0, |chars| chars.len())
+    }
+
+    fn remove_chars(&mut self, y: usize, start: usize, end: usize) {
+        if let Some(chars) = self.row(y) {
+            let end = end.min(chars.len());
+            chars.drain(start.min(end)..end);
+        }
+    }
+
+    fn remove_row(&mut self, y: usize) -> Option<Vec<Option<char>>> {
+        let index = self.index(y);
+        (index < self.rows.len()).then(|| self.rows.remove(index).chars)
+    }
+
+    fn join_lines(&mut self, y: usize) {
+        if y + 1 >= self.shown_len() {
+            return;
+        }
+        let next = self.remove_row(y + 1).unwrap_or_default();
+        if let Some(chars) = self.row(y) {
+            chars.extend(next);
+        }
+    }
+
+    /// Adds the rows and the blanks up to the cursor, like moving the cursor
+    /// there does in the editor.
+    fn update_cursor(&mut self) {
+        while self.y >= self.shown_len() {
+            self.rows.push(Row {
+                chars: vec![],
+                shown: true,
+            });
+        }
+        let x = self.x;
+        if let Some(chars) = self.row(self.y) {
+            if chars.len() < x {
+                chars.resize(x, None);
+            }
+        }
+    }
+
+    fn apply(&mut self, inst: &Instruction) {
+        match inst {
+            Instruction::MoveCursor(x, y) => {
+                (self.x, self.y) = (*x as usize, *y as usize);
+                self.update_cursor();
+            }
+            Instruction::Type(c, _) => {
+                let x = self.x;
+                if let Some(chars) = self.row(self.y) {
+                    chars.insert(x, Some(*c));
+                }
+                self.x += 1;
+                self.update_cursor();
+            }
+            Instruction::InsertLine => {
+                let index = self.index(self.y);
+                let row = Row {
+                    chars: vec![],
+                    shown: true,
+                };
+                self.rows.insert(index, row);
+                self.x = 0;
+                self.update_cursor();
+            }
+            Instruction::DeleteLine => {
+                self.remove_row(self.y);
+                self.x = 0;
+                self.update_cursor();
+            }
+            Instruction::Backspace => {
+                if self.x > 0 {
+                    self.x -= 1;
+                    self.remove_chars(self.y, self.x, self.x + 1);
+                } else if self.y > 0 {
+                    self.x = self.line_len(self.y - 1);
+                    self.y -= 1;
+                    self.join_lines(self.y);
+                }
+                self.update_cursor();
+            }
+            Instruction::DeleteForward => {
+                match self.x < self.line_len(self.y) {
+                    true => self.remove_chars(self.y, self.x, self.x + 1),
+                    false => self.join_lines(self.y),
+                }
+                self.update_cursor();
+            }
+            Instruction::DeleteRange { from, to } => {
+                let (start, end) = (
+                    (from.0 as usize, from.1 as usize),
+                    (to.0 as usize, to.1 as usize),
+                );
+                if start.1 == end.1 {
+                    self.remove_chars(start.1, start.0, end.0);
+                } else if start.1 < end.1 {
+                    self.remove_chars(start.1, start.0, usize::MAX);
+                    self.remove_chars(end.1, 0, end.0);
+                    for _ in start.1 + 1..end.1.min(self.shown_len()) {
+                        self.remove_row(start.1 + 1);
+                    }
+                    self.join_lines(start.1);
+                }
+                (self.x, self.y) = start;
+                self.update_cursor();
+            }
+            Instruction::JoinLines => {
+                self.x = self.line_len(self.y);
+                self.join_lines(self.y);
+                self.update_cursor();
+            }
+            Instruction::Newline { x } => {
+                self.x = *x as usize;
+                self.y += 1;
+                self.update_cursor();
+            }
+            Instruction::SetX(x) => {
+                self.x = *x as usize;
+                self.update_cursor();
+            }
+            Instruction::FileOnly(line) => {
+                let index = self.index(self.y);
+                let row = Row {
+                    chars: line.chars().map(Some).collect(),
+                    shown: false,
+                };
+                self.rows.insert(index, row);
+            }
+            Instruction::Instant(instructions) => {
+                instructions.iter().for_each(|inst| self.apply(inst));
+            }
+            Instruction::SetForeground(_)
+            | Instruction::Pause(_)
+            | Instruction::SetSpeed(_)
+            | Instruction::Highlight(_)
+            | Instruction::Wait
+            | Instruction::WaitForQuit
+            | Instruction::UpdateState(..)
+            | Instruction::HideCursor => (),
+        }
+    }
+}
+
+/// The text the editor shows once it followed `instructions`, along with the
+/// lines that were left out of it, which is what the edited file contains.
+/// The lines are separated by line breaks, without one after the last.
+pub fn render(instructions: &[Instruction]) -> String {
+    let mut document = Document {
+        rows: vec![],
+        x: 0,
+        y: 0,
+    };
+    instructions.iter().for_each(|inst| document.apply(inst));
+
+    let mut lines = vec![];
+    for mut row in document.rows {
+        // blanks are spaces, unless nothing was typed after them
+        while let Some(None) = row.chars.last() {
+            row.chars.pop();
+        }
+        lines.push(
+            row.chars
+                .into_iter()
+                .map(|c| c.unwrap_or(' '))
+                .collect::<String>(),
+        );
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use syntect::highlighting::ThemeSet;
+
+    use super::render;
+    use crate::parse::Parser;
+    use crate::syntax;
+
+    fn written(src: &str) -> String {
+        let theme = &ThemeSet::load_defaults().themes["base16-ocean.dark"];
+        let lines = syntax::highlight(src, "main.rs", theme);
+        let instructions = Parser::new(lines, syntax::comment("main.rs"))
+            .instructions()
+            .unwrap();
+        render(&instructions)
+    }
+
+    #[test]
+    fn writes_lines_after_a_goto_where_they_are_shown() {
+        let src = "fn main() {\n}\n//[GOTO 1]\nuse std::io;\n\n";
+        assert_eq!(written(src), "use std::io;\n\nfn main() {\n}\n");
+    }
+
+    #[test]
+    fn writes_lines_typed_in_order_as_they_are() {
+        let src = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(written(src), src);
+    }
+
+    #[test]
+    fn writes_lines_after_edits() {
+        let src = "let x = todo!();\n//[BACKSPACE 8]\n1;\n";
+        assert_eq!(written(src), "let x = 1;\n");
+    }
+
+    #[test]
+    fn writes_deleted_and_joined_lines_as_they_end_up() {
+        let src = "a\nb\n//[DELETE LINE]\nc\n//[JOIN]\nd\n";
+        assert_eq!(written(src), "a\ncd\n");
+    }
+
+    #[test]
+    fn writes_a_deleted_range() {
+        let src = "one\ntwo\nthree\n//[DELETE 1:2-2:3]\n";
+        assert_eq!(written(src), "o\nthree");
+    }
+
+    #[test]
+    fn writes_skipped_lines_without_showing_them() {
+        let src = "a\n//[SKIP]\nhidden\n//[/SKIP]\nb\n";
+        assert_eq!(written(src), "a\nhidden\nb\n");
+    }
+}
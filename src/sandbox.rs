@@ -0,0 +1,109 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use rand::Rng;
+
+/// The sandbox's path for the signal handler, which can't allocate.
+static SANDBOX_PATH: OnceLock<CString> = OnceLock::new();
+
+/// A fresh temporary directory for a scene to run in. It is removed again when
+/// this is dropped, unless it should be kept.
+pub struct Sandbox {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Sandbox {
+    /// Creates the directory and copies the contents of `fixture` into it.
+    pub fn create(fixture: Option<&Path>, keep: bool) -> io::Result<Self> {
+        let path = loop {
+            let name = format!("scammed-{:08x}", rand::thread_rng().gen::<u32>());
+            let path = std::env::temp_dir().join(name);
+            match fs::create_dir(&path) {
+                Ok(()) => break path,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        let sandbox = Self { path, keep };
+        if let Some(fixture) = fixture {
+            copy_dir(fixture, &sandbox.path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to copy {}: {e}", fixture.display()),
+                )
+            })?;
+        }
+        if !keep {
+            if let Ok(path) = CString::new(sandbox.path.as_os_str().as_bytes()) {
+                _ = SANDBOX_PATH.set(path);
+            }
+        }
+        Ok(sandbox)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if self.keep {
+            eprintln!("The sandbox was kept at {}", self.path.display());
+        } else if let Err(e) = fs::remove_dir_all(&self.path) {
+            eprintln!("Failed to remove the sandbox {}: {e}", self.path.display());
+        }
+    }
+}
+
+/// Copies the contents of `src` into `dst`, keeping symlinks as they are.
+fn copy_dir(src: &Path, dst: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = dst.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            fs::create_dir(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the sandbox when scammed is killed by a signal, which skips dropping it.
+/// Only calls async-signal-safe functions.
+pub fn remove_from_signal_handler() {
+    let Some(path) = SANDBOX_PATH.get() else {
+        return;
+    };
+    let rm = c"/bin/rm";
+    let args = [
+        rm.as_ptr(),
+        c"-rf".as_ptr(),
+        c"--".as_ptr(),
+        path.as_ptr(),
+        std::ptr::null(),
+    ];
+    unsafe {
+        match libc::fork() {
+            0 => {
+                libc::execv(rm.as_ptr(), args.as_ptr());
+                libc::_exit(127);
+            }
+            -1 => (),
+            pid => {
+                libc::waitpid(pid, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
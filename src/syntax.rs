@@ -1,10 +1,97 @@
+use std::path::Path;
+
 use anathema::default_widgets::CanvasAttribs;
 use anathema::state::Hex;
 use syntect::easy::HighlightLines;
 use syntect::highlighting::{FontStyle, Style, Theme, ThemeSet};
-use syntect::parsing::SyntaxSet;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
 use syntect::util::LinesWithEndings;
 
+/// How a comment is written in a language, which is what directives like
+/// `//[WAIT]` are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comment {
+    pub start: &'static str,
+    // closes the comment, empty if it goes on to the end of the line
+    pub end: &'static str,
+}
+
+const SLASHES: Comment = Comment {
+    start: "//",
+    end: "",
+};
+const HASH: Comment = Comment {
+    start: "#",
+    end: "",
+};
+const DASHES: Comment = Comment {
+    start: "--",
+    end: "",
+};
+const SEMICOLON: Comment = Comment {
+    start: ";",
+    end: "",
+};
+const PERCENT: Comment = Comment {
+    start: "%",
+    end: "",
+};
+const MARKUP: Comment = Comment {
+    start: "<!--",
+    end: "-->",
+};
+const BLOCK: Comment = Comment {
+    start: "/*",
+    end: "*/",
+};
+
+/// The comments of the syntaxes that don't use `//`, by the syntax's name.
+const SYNTAX_COMMENTS: &[(&str, Comment)] = &[
+    ("Bourne Again Shell (bash)", HASH),
+    ("Python", HASH),
+    ("Ruby", HASH),
+    ("Perl", HASH),
+    ("R", HASH),
+    ("Makefile", HASH),
+    ("YAML", HASH),
+    ("Tcl", HASH),
+    ("Java Properties", HASH),
+    ("SQL", DASHES),
+    ("Lua", DASHES),
+    ("Haskell", DASHES),
+    ("Lisp", SEMICOLON),
+    ("Clojure", SEMICOLON),
+    ("Erlang", PERCENT),
+    ("LaTeX", PERCENT),
+    ("TeX", PERCENT),
+    ("MATLAB", PERCENT),
+    ("HTML", MARKUP),
+    ("XML", MARKUP),
+    ("Markdown", MARKUP),
+    ("CSS", BLOCK),
+    (
+        "OCaml",
+        Comment {
+            start: "(*",
+            end: "*)",
+        },
+    ),
+];
+
+/// Comments by file name or extension, which win over the syntax's. Files named
+/// like this but without a syntax of their own are shown as plain text.
+const FILE_COMMENTS: &[(&str, Comment)] = &[
+    ("toml", HASH),
+    ("ini", SEMICOLON),
+    ("cfg", HASH),
+    ("conf", HASH),
+    ("env", HASH),
+    (".env", HASH),
+    (".gitignore", HASH),
+    (".dockerignore", HASH),
+    ("Dockerfile", HASH),
+];
+
 #[derive(Debug)]
 pub struct Span<'a> {
     pub src: &'a str,
@@ -15,7 +102,6 @@ pub struct Span<'a> {
 impl<'a> Span<'a> {
     pub fn take_space(&self) -> (Option<i32>, &str, bool) {
         let count = self.src.bytes().take_while(|b| *b == b' ').count();
-        
 
         let opt_count = match count {
             0 => None,
@@ -40,14 +126,26 @@ pub struct Line<'a> {
     pub tail: Box<[Span<'a>]>,
 }
 
-pub fn highlight<'a>(src: &'a str, ext: &str, theme: &Theme) -> Box<[Line<'a>]> {
+impl Line<'_> {
+    /// The source of the line, including its line break.
+    pub fn text(&self) -> String {
+        let mut text = self.head.src.to_string();
+        for span in &*self.tail {
+            text.push_str(span.src);
+        }
+        text
+    }
+}
+
+/// Highlights `src` as the contents of a file called `name`.
+pub fn highlight<'a>(src: &'a str, name: &str, theme: &Theme) -> Box<[Line<'a>]> {
     let ps = SyntaxSet::load_defaults_newlines();
     let ts = ThemeSet::load_defaults();
 
     // let ts = ThemeSet::load_defaults();
     // let theme = &ts.themes["base16-eighties.dark"];
 
-    let syntax = ps.find_syntax_by_extension(ext).unwrap();
+    let syntax = find_syntax(&ps, name).unwrap();
     let mut h = HighlightLines::new(syntax, &theme);
 
     let mut output = vec![];
@@ -72,3 +170,42 @@ pub fn highlight<'a>(src: &'a str, ext: &str, theme: &Theme) -> Box<[Line<'a>]>
 
     output.into_boxed_slice()
 }
+
+/// Whether files called `name` can be highlighted.
+pub fn supports_file(name: &str) -> bool {
+    find_syntax(&SyntaxSet::load_defaults_newlines(), name).is_some()
+}
+
+/// How comments are written in files called `name`, `//` if it isn't known.
+pub fn comment(name: &str) -> Comment {
+    let by_name = lookups(name).find_map(|key| FILE_COMMENTS.iter().find(|(k, _)| *k == key));
+    if let Some((_, comment)) = by_name {
+        return *comment;
+    }
+    let ps = SyntaxSet::load_defaults_newlines();
+    let Some(syntax) = find_syntax(&ps, name) else {
+        return SLASHES;
+    };
+    SYNTAX_COMMENTS
+        .iter()
+        .find(|(name, _)| *name == syntax.name)
+        .map_or(SLASHES, |(_, comment)| *comment)
+}
+
+fn find_syntax<'a>(ps: &'a SyntaxSet, name: &str) -> Option<&'a SyntaxReference> {
+    lookups(name).find_map(|key| {
+        ps.find_syntax_by_extension(key).or_else(|| {
+            FILE_COMMENTS
+                .iter()
+                .any(|(k, _)| *k == key)
+                .then(|| ps.find_syntax_plain_text())
+        })
+    })
+}
+
+/// What a file called `name` is looked up by: its whole name, like `Makefile`
+/// or `.gitignore`, and then its extension.
+fn lookups(name: &str) -> impl Iterator<Item = &str> {
+    let extension = Path::new(name).extension().and_then(|ext| ext.to_str());
+    std::iter::once(name).chain(extension)
+}